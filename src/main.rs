@@ -1,20 +1,49 @@
 use anyhow::{Context, Result};
 use eframe::egui::{
-    self, Color32, FontFamily, FontId,
+    self, Color32, FontFamily, FontId, Galley,
     text::{LayoutJob, TextFormat},
 };
 use portable_pty::{CommandBuilder, PtySize, native_pty_system};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, VecDeque},
     fs,
-    io::{Read, Write},
+    io::{IsTerminal, Read, Write},
     mem,
-    sync::{Arc, Mutex},
+    ops::{Bound, Range},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+const DEFAULT_PROFILE: &str = "default";
+const DEFAULT_TITLE: &str = "YATE";
+const COMMAND_RESULT_DISPLAY_DURATION: Duration = Duration::from_secs(3);
+const CONTROL_CHORD_DISPLAY_DURATION: Duration = Duration::from_millis(800);
+const PALETTE_SAVE_DEBOUNCE: Duration = Duration::from_secs(2);
+const BELL_FLASH_DURATION: Duration = Duration::from_millis(100);
+const PROMPT_FLASH_DURATION: Duration = Duration::from_millis(400);
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const PROCESS_NAME_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MIN_WINDOW_SIZE: egui::Vec2 = egui::vec2(200.0, 100.0);
+const MAX_WINDOW_SIZE: egui::Vec2 = egui::vec2(16384.0, 16384.0);
+const TITLE_STACK_LIMIT: usize = 10;
+const FAINT_BLEND: f32 = 0.4;
+const FAUX_BOLD_OFFSET: f32 = 1.0;
+const FONT_SIZE_STEP: f32 = 1.0;
+const MIN_ZOOM_FONT_SIZE: f32 = 6.0;
+const MAX_ZOOM_FONT_SIZE: f32 = 72.0;
+const MIN_BUFFERED_OUTPUT: usize = 4096;
+const STICK_TO_BOTTOM_TOLERANCE: f32 = 1.0;
+const SYNCHRONIZED_OUTPUT_TIMEOUT: Duration = Duration::from_secs(1);
+const SHARED_READER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ColorPalette {
     background: [u8; 4],
     white: [u8; 4],
@@ -160,325 +189,7777 @@ impl From<ColorPalette> for Colors {
     }
 }
 
-fn load_colors(path: &str) -> Result<ColorPalette> {
-    let toml_string = fs::read_to_string(path).context("Failed to read colors.toml file")?;
-    let palette = toml::from_str(&toml_string).context("Failed to parse colors.toml")?;
-    Ok(palette)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum InputMode {
+    Line,
+    Raw,
 }
 
-fn save_colors(path: &str, colors: &ColorPalette) -> Result<()> {
-    let toml_string =
-        toml::to_string_pretty(colors).context("Failed to serialize colors to TOML")?;
-    fs::write(path, toml_string).context("Failed to write to colors.toml")?;
-    Ok(())
+impl InputMode {
+    fn toggled(self) -> Self {
+        match self {
+            InputMode::Line => InputMode::Raw,
+            InputMode::Raw => InputMode::Line,
+        }
+    }
 }
 
-struct Colors {
-    background: Color32,
-    white: Color32,
-    black: Color32,
-    red: Color32,
-    green: Color32,
-    yellow: Color32,
-    blue: Color32,
-    magenta: Color32,
-    cyan: Color32,
-    grey: Color32,
-    bright_red: Color32,
-    bright_green: Color32,
-    bright_yellow: Color32,
-    bright_blue: Color32,
-    bright_magenta: Color32,
-    bright_cyan: Color32,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FocusTarget {
+    Input,
+    Terminal,
 }
 
-struct TerminalApp {
-    output_buffer: Arc<Mutex<Vec<u8>>>,
-    writer: Arc<Mutex<Box<dyn Write + Send>>>,
-    _master_pty: Arc<Mutex<Box<dyn portable_pty::MasterPty + Send>>>,
-    layout_job: LayoutJob,
-    input_text: String,
-    stick_to_bottom: bool,
-    current_format: TextFormat,
-    partial_char_buffer: Vec<u8>,
-    colors: Colors,
+fn focus_target(preferred: FocusTarget, input_mode: InputMode) -> FocusTarget {
+    match input_mode {
+        InputMode::Line => preferred,
+        InputMode::Raw => FocusTarget::Terminal,
+    }
 }
 
-impl TerminalApp {
-    fn append_new_output(&mut self, new_output: &[u8]) {
-        let mut text_to_append = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BellStyle {
+    Visual,
+    None,
+}
 
-        for &byte in new_output {
-            match byte {
-                b'\x1b' => {
-                    if !text_to_append.is_empty() {
-                        self.layout_job.append(
-                            &String::from_utf8_lossy(&text_to_append),
-                            0.0,
-                            self.current_format.clone(),
-                        );
-                        text_to_append.clear();
-                    }
-                    self.partial_char_buffer.push(byte);
-                }
-                b'[' if self.partial_char_buffer.last() == Some(&b'\x1b') => {
-                    self.partial_char_buffer.push(byte);
-                }
-                b'\n' | b'\r' => {
-                    if !text_to_append.is_empty() {
-                        self.layout_job.append(
-                            &String::from_utf8_lossy(&text_to_append),
-                            0.0,
-                            self.current_format.clone(),
-                        );
-                        text_to_append.clear();
-                    }
-                    self.layout_job.append(
-                        &String::from_utf8_lossy(&[byte]),
-                        0.0,
-                        self.current_format.clone(),
-                    );
-                }
-                _ if !self.partial_char_buffer.is_empty() => {
-                    self.partial_char_buffer.push(byte);
-                    if let Some(command_char) = self.partial_char_buffer.last() {
-                        if command_char.is_ascii_alphabetic() {
-                            let ansi_sequence = String::from_utf8_lossy(&self.partial_char_buffer);
-                            if ansi_sequence.ends_with('m') {
-                                if let Some(start_index) = ansi_sequence.find('[') {
-                                    let code_str =
-                                        &ansi_sequence[start_index + 1..ansi_sequence.len() - 1];
-                                    for part in code_str.split(';') {
-                                        if let Ok(num) = part.parse::<u32>() {
-                                            match num {
-                                                0 => {
-                                                    self.current_format.color = self.colors.white;
-                                                    self.current_format.underline =
-                                                        egui::Stroke::NONE;
-                                                }
-                                                30 => self.current_format.color = self.colors.black,
-                                                31 => self.current_format.color = self.colors.red,
-                                                32 => self.current_format.color = self.colors.green,
-                                                33 => {
-                                                    self.current_format.color = self.colors.yellow
-                                                }
-                                                34 => self.current_format.color = self.colors.blue,
-                                                35 => {
-                                                    self.current_format.color = self.colors.magenta
-                                                }
-                                                36 => self.current_format.color = self.colors.cyan,
-                                                37 => self.current_format.color = self.colors.white,
-                                                90 => self.current_format.color = self.colors.grey,
-                                                91 => {
-                                                    self.current_format.color =
-                                                        self.colors.bright_red
-                                                }
-                                                92 => {
-                                                    self.current_format.color =
-                                                        self.colors.bright_green
-                                                }
-                                                93 => {
-                                                    self.current_format.color =
-                                                        self.colors.bright_yellow
-                                                }
-                                                94 => {
-                                                    self.current_format.color =
-                                                        self.colors.bright_blue
-                                                }
-                                                95 => {
-                                                    self.current_format.color =
-                                                        self.colors.bright_magenta
-                                                }
-                                                96 => {
-                                                    self.current_format.color =
-                                                        self.colors.bright_cyan
-                                                }
-                                                97 => self.current_format.color = self.colors.white,
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            self.partial_char_buffer.clear();
-                        }
-                    }
-                }
-                _ => {
-                    if !self.partial_char_buffer.is_empty() {
-                        self.layout_job.append(
-                            &String::from_utf8_lossy(&self.partial_char_buffer),
-                            0.0,
-                            self.current_format.clone(),
-                        );
-                        self.partial_char_buffer.clear();
-                    }
-                    text_to_append.push(byte);
-                }
-            }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ShellExitAction {
+    Close,
+    Notice,
+    Respawn,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum UnprintablePolicy {
+    Placeholder,
+    Hex,
+    Drop,
+}
+
+fn history_step(len: usize, index: Option<usize>, older: bool) -> Option<usize> {
+    match (index, older) {
+        (None, true) => len.checked_sub(1),
+        (Some(index), true) => Some(index.saturating_sub(1)),
+        (Some(index), false) if index + 1 < len => Some(index + 1),
+        (_, false) => None,
+    }
+}
+
+fn enforce_input_limit(text: &mut String, max_chars: Option<usize>) -> bool {
+    let Some(max_chars) = max_chars else {
+        return false;
+    };
+    if let Some((byte_index, _)) = text.char_indices().nth(max_chars) {
+        text.truncate(byte_index);
+    }
+    text.chars().count() >= max_chars
+}
+
+fn replace_unprintable(text: &str, policy: UnprintablePolicy) -> String {
+    let mut replaced = String::with_capacity(text.len());
+    for c in text.chars() {
+        let unprintable = (c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+            || c == char::REPLACEMENT_CHARACTER;
+        if !unprintable {
+            replaced.push(c);
+            continue;
         }
-        if !text_to_append.is_empty() {
-            self.layout_job.append(
-                &String::from_utf8_lossy(&text_to_append),
-                0.0,
-                self.current_format.clone(),
-            );
+        match policy {
+            UnprintablePolicy::Placeholder => replaced.push('·'),
+            UnprintablePolicy::Hex if c == char::REPLACEMENT_CHARACTER => replaced.push_str("<?>"),
+            UnprintablePolicy::Hex => replaced.push_str(&format!("<{:02X}>", c as u32)),
+            UnprintablePolicy::Drop => {}
         }
     }
+    replaced
 }
 
-impl eframe::App for TerminalApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let frame = egui::Frame::central_panel(&ctx.style()).fill(self.colors.background);
-        egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
-            let new_output = {
-                let mut output_buffer = self.output_buffer.lock().unwrap();
-                mem::take(&mut *output_buffer)
-            };
-            if !new_output.is_empty() {
-                self.append_new_output(&new_output);
+fn expand_tabs(text: &str, start_column: usize, tab_width: usize) -> String {
+    let tab_width = tab_width.max(1);
+    let mut column = start_column;
+    let mut expanded = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\t' => {
+                let spaces = tab_width - column % tab_width;
+                expanded.extend(std::iter::repeat_n(' ', spaces));
+                column += spaces;
             }
-            let scroll_area_response = egui::ScrollArea::vertical()
-                .stick_to_bottom(self.stick_to_bottom)
-                .show(ui, |ui| {
-                    ui.add(egui::Label::new(self.layout_job.clone()));
-                });
-
-            let max_offset_y =
-                scroll_area_response.content_size.y - scroll_area_response.inner_rect.height();
-            if scroll_area_response.state.offset.y < max_offset_y - 1.0 {
-                self.stick_to_bottom = false;
+            '\n' | '\r' => {
+                expanded.push(c);
+                column = 0;
+            }
+            _ => {
+                expanded.push(c);
+                column += 1;
             }
+        }
+    }
+    expanded
+}
 
-            let text_edit_response = ui.add(
-                egui::TextEdit::singleline(&mut self.input_text)
-                    .desired_width(ui.available_width())
-                    .hint_text("Type commands here...")
-                    .font(egui::TextStyle::Monospace)
-                    .margin(egui::Margin::symmetric(5, 5)),
-            );
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ClearMode {
+    Truncate,
+    Scroll,
+}
 
-            if text_edit_response.lost_focus() && ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
-                let mut writer = self.writer.lock().unwrap();
-                let _ = writer.write_all(self.input_text.as_bytes());
-                let _ = writer.write_all(b"\n");
-                let _ = writer.flush();
-                self.input_text.clear();
-                self.stick_to_bottom = true;
-                text_edit_response.request_focus();
-            }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PagerCommand {
+    PageDown,
+    PageUp,
+    Quit,
+}
 
-            ctx.request_repaint_after(Duration::from_millis(1));
-        });
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScrollCommand {
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
 }
 
-fn main() -> Result<()> {
-    let pty_system = native_pty_system();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum FontFallback {
+    Monospace,
+    Proportional,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct GridSize {
+    rows: u16,
+    cols: u16,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ShellConfig {
+    program: Option<String>,
+    args: Vec<String>,
+    cwd: Option<PathBuf>,
+}
 
-    let shell_path = if cfg!(target_os = "windows") {
+fn default_shell() -> String {
+    if cfg!(target_os = "windows") {
         "cmd.exe".to_string()
     } else {
         std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string())
-    };
+    }
+}
+
+fn shell_invocation(shell: &ShellConfig, passthrough: &[String]) -> (String, Vec<String>) {
+    match passthrough.split_first() {
+        Some((program, args)) => (program.clone(), args.to_vec()),
+        None => (
+            shell.program.clone().unwrap_or_else(default_shell),
+            shell.args.clone(),
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct Config {
+    smart_newline: bool,
+    show_scroll_percentage: bool,
+    input_mode: InputMode,
+    input_mode_toggle_key: String,
+    highlight_cursor_line: bool,
+    synchronized_output: bool,
+    max_osc_payload: usize,
+    issue_url_template: Option<String>,
+    commit_url_template: Option<String>,
+    background_image: Option<String>,
+    background_image_opacity: f32,
+    profiles: BTreeMap<String, ColorPalette>,
+    profile_cycle_key: String,
+    remember_profile: bool,
+    wrap_lines: bool,
+    flash_prompt: bool,
+    strip_ansi_input: bool,
+    hex_dump_toggle_key: String,
+    hex_dump_bytes: usize,
+    clear_mode: ClearMode,
+    initial_focus: FocusTarget,
+    unfocused_repaint_ms: u64,
+    show_command_duration: bool,
+    unprintable: UnprintablePolicy,
+    copy_command_output_key: String,
+    autosave_palette: bool,
+    show_status_line: bool,
+    fixed_grid: Option<GridSize>,
+    draw_cursor: bool,
+    transparent_background: bool,
+    title_from_command: bool,
+    scrollback_lines: usize,
+    tab_scrollback_lines: Vec<usize>,
+    scrollback_trim_per_frame: usize,
+    kitty_keyboard: bool,
+    tab_width: usize,
+    search_as_you_type: bool,
+    clear_scrollback: bool,
+    highlight_prompt: bool,
+    answer_decrqss: bool,
+    report_window_size: bool,
+    kinetic_scrolling: bool,
+    kinetic_decay: f32,
+    title_stack: bool,
+    answerback: String,
+    show_control_chords: bool,
+    separate_stderr: bool,
+    stderr_color: Option<[u8; 4]>,
+    osc8_links: bool,
+    max_input_length: Option<usize>,
+    underline_styles: bool,
+    font_size: f32,
+    min_font_size: f32,
+    collapse_progress: bool,
+    notify_patterns: Vec<String>,
+    invert_cursor_glyph: bool,
+    cursor_text_color: Option<[u8; 4]>,
+    shell: ShellConfig,
+    window: Option<WindowGeometry>,
+    import_palettes: Vec<PathBuf>,
+    submit_every_enter: bool,
+    stdin_pane: bool,
+    font_path: Option<PathBuf>,
+    font_fallback: FontFallback,
+    preserve_scroll_on_resize: bool,
+    pager_key: String,
+    pager_burst_lines: Option<usize>,
+    max_buffered_output: usize,
+    mirror_output: bool,
+    mirror_key: String,
+    search_case_sensitive: bool,
+    max_bytes_per_frame: Option<usize>,
+    bell: BellStyle,
+    export_sgr: bool,
+    on_shell_exit: ShellExitAction,
+    shared_reader: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            smart_newline: true,
+            show_scroll_percentage: true,
+            input_mode: InputMode::Line,
+            input_mode_toggle_key: "F12".to_string(),
+            highlight_cursor_line: false,
+            synchronized_output: true,
+            max_osc_payload: 1024 * 1024,
+            issue_url_template: None,
+            commit_url_template: None,
+            background_image: None,
+            background_image_opacity: 0.3,
+            profiles: BTreeMap::new(),
+            profile_cycle_key: "F9".to_string(),
+            remember_profile: true,
+            wrap_lines: true,
+            flash_prompt: false,
+            strip_ansi_input: false,
+            hex_dump_toggle_key: "F8".to_string(),
+            hex_dump_bytes: 4096,
+            clear_mode: ClearMode::Scroll,
+            initial_focus: FocusTarget::Input,
+            unfocused_repaint_ms: 250,
+            show_command_duration: false,
+            unprintable: UnprintablePolicy::Placeholder,
+            copy_command_output_key: "F7".to_string(),
+            autosave_palette: false,
+            show_status_line: false,
+            fixed_grid: None,
+            draw_cursor: true,
+            transparent_background: false,
+            title_from_command: false,
+            scrollback_lines: 10_000,
+            tab_scrollback_lines: Vec::new(),
+            scrollback_trim_per_frame: 200,
+            kitty_keyboard: false,
+            tab_width: 8,
+            search_as_you_type: true,
+            clear_scrollback: true,
+            highlight_prompt: false,
+            answer_decrqss: true,
+            report_window_size: true,
+            kinetic_scrolling: false,
+            kinetic_decay: 0.92,
+            title_stack: true,
+            answerback: String::new(),
+            show_control_chords: false,
+            separate_stderr: false,
+            stderr_color: None,
+            osc8_links: true,
+            max_input_length: None,
+            underline_styles: true,
+            font_size: 14.0,
+            min_font_size: 4.0,
+            collapse_progress: true,
+            notify_patterns: Vec::new(),
+            invert_cursor_glyph: true,
+            cursor_text_color: None,
+            shell: ShellConfig::default(),
+            window: None,
+            import_palettes: Vec::new(),
+            submit_every_enter: true,
+            stdin_pane: true,
+            font_path: None,
+            font_fallback: FontFallback::Monospace,
+            preserve_scroll_on_resize: true,
+            pager_key: "F6".to_string(),
+            pager_burst_lines: None,
+            max_buffered_output: 1024 * 1024,
+            mirror_output: false,
+            mirror_key: "F5".to_string(),
+            search_case_sensitive: false,
+            max_bytes_per_frame: None,
+            bell: BellStyle::Visual,
+            export_sgr: false,
+            on_shell_exit: ShellExitAction::Notice,
+            shared_reader: false,
+        }
+    }
+}
+
+fn tab_scrollback_lines(config: &Config, tab: usize) -> usize {
+    (config.tab_scrollback_lines.get(tab).copied()).unwrap_or(config.scrollback_lines)
+}
 
-    let initial_pty_size = PtySize {
-        rows: 25,
-        cols: 80,
-        pixel_width: 640,
-        pixel_height: 375,
+fn config_file_path(file_name: &str, create_dir: bool) -> PathBuf {
+    let legacy = PathBuf::from(file_name);
+    let Some(dir) = dirs::config_dir().map(|dir| dir.join("YATE")) else {
+        return legacy;
     };
+    let path = dir.join(file_name);
+    if !path.exists() && legacy.exists() {
+        return legacy;
+    }
+    if create_dir && let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Failed to create {}: {e}", dir.display());
+        return legacy;
+    }
+    path
+}
 
-    let pty_pair = pty_system.openpty(initial_pty_size)?;
+fn load_config(path: &Path) -> Result<Config> {
+    let toml_string = fs::read_to_string(path).context("Failed to read colors.toml file")?;
+    let config = toml::from_str(&toml_string).context("Failed to parse colors.toml")?;
+    Ok(config)
+}
 
-    let cmd = CommandBuilder::new(shell_path);
+fn validate_background_image(path: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(path);
+    if !path.is_file() {
+        anyhow::bail!("{} is not a file", path.display());
+    }
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("png" | "jpg" | "jpeg") => Ok(path),
+        _ => anyhow::bail!("{} is not a PNG or JPEG image", path.display()),
+    }
+}
 
-    let _child = pty_pair.slave.spawn_command(cmd)?;
+fn load_font(path: &Path, fallback: FontFallback) -> Result<egui::FontDefinitions> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    ab_glyph::FontRef::try_from_slice(&bytes)
+        .with_context(|| format!("{} is not a usable font", path.display()))?;
+    let mut fonts = egui::FontDefinitions::default();
+    fonts.font_data.insert(
+        "user_font".to_string(),
+        Arc::new(egui::FontData::from_owned(bytes)),
+    );
+    let fallback_family = match fallback {
+        FontFallback::Monospace => FontFamily::Monospace,
+        FontFallback::Proportional => FontFamily::Proportional,
+    };
+    let mut family = fonts
+        .families
+        .get(&fallback_family)
+        .cloned()
+        .unwrap_or_default();
+    family.insert(0, "user_font".to_string());
+    fonts.families.insert(FontFamily::Monospace, family);
+    Ok(fonts)
+}
 
-    let master = pty_pair.master;
-    let writer = master.take_writer()?;
-    let mut reader = master.try_clone_reader()?;
+fn load_background_image(path: &str) -> Result<egui::ColorImage> {
+    let path = validate_background_image(path)?;
+    let image = image::open(&path)
+        .with_context(|| format!("Failed to decode {}", path.display()))?
+        .to_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    Ok(egui::ColorImage::from_rgba_unmultiplied(
+        size,
+        image.as_flat_samples().as_slice(),
+    ))
+}
 
-    let output_buffer = Arc::new(Mutex::new(Vec::new()));
-    let app_writer = Arc::new(Mutex::new(writer));
-    let app_master_pty = Arc::new(Mutex::new(master));
-    let cloned_output_buffer = output_buffer.clone();
+fn clamp_opacity(opacity: f32) -> f32 {
+    if opacity.is_nan() {
+        1.0
+    } else {
+        opacity.clamp(0.0, 1.0)
+    }
+}
 
-    thread::spawn(move || {
-        let mut buffer = [0; 1024];
-        loop {
-            match reader.read(&mut buffer) {
-                Ok(count) if count > 0 => {
-                    let mut output = cloned_output_buffer.lock().unwrap();
-                    output.extend_from_slice(&buffer[..count]);
-                }
-                Ok(_) => {
-                    break;
-                }
-                Err(e) => {
-                    eprintln!("Error reading from PTY: {e}");
-                    break;
-                }
-            }
-            thread::sleep(Duration::from_millis(1));
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    active_profile: Option<String>,
+    window: Option<WindowGeometry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct WindowGeometry {
+    width: f32,
+    height: f32,
+    x: Option<f32>,
+    y: Option<f32>,
+}
+
+impl WindowGeometry {
+    fn clamped(self) -> Self {
+        let finite = |value: f32, fallback: f32| if value.is_finite() { value } else { fallback };
+        Self {
+            width: finite(self.width, 1920.0).clamp(MIN_WINDOW_SIZE.x, MAX_WINDOW_SIZE.x),
+            height: finite(self.height, 1080.0).clamp(MIN_WINDOW_SIZE.y, MAX_WINDOW_SIZE.y),
+            x: self.x.filter(|x| x.is_finite()),
+            y: self.y.filter(|y| y.is_finite()),
         }
-    });
+    }
+}
 
-    let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_resizable(true)
-            .with_inner_size(egui::vec2(1920.0, 1080.0)),
-        ..Default::default()
-    };
-    eframe::run_native(
-        "YATE",
-        options,
-        Box::new(|_cc| {
-            let colors = match load_colors("colors.toml") {
-                Ok(palette) => Colors::from(palette),
-                Err(e) => {
-                    eprintln!("Failed to load colors.toml: {e}. Using default colors.");
-                    let default_colors = Colors {
-                        background: Color32::from_rgb(20, 20, 20),
-                        white: Color32::WHITE,
-                        black: Color32::BLACK,
-                        red: Color32::RED,
-                        green: Color32::GREEN,
-                        yellow: Color32::YELLOW,
-                        blue: Color32::BLUE,
-                        magenta: Color32::MAGENTA,
-                        cyan: Color32::CYAN,
-                        grey: Color32::GRAY,
-                        bright_red: Color32::LIGHT_RED,
-                        bright_green: Color32::LIGHT_GREEN,
-                        bright_yellow: Color32::LIGHT_YELLOW,
-                        bright_blue: Color32::LIGHT_BLUE,
-                        bright_magenta: Color32::PURPLE,
-                        bright_cyan: Color32::DARK_BLUE,
-                    };
-                    let _ = save_colors("colors.toml", &ColorPalette::from(&default_colors));
-                    default_colors
-                }
-            };
+fn estimated_pty_size(size: egui::Vec2, font_size: f32) -> PtySize {
+    let (rows, cols) = grid_dimensions(size, font_size * 0.6, font_size * 1.2);
+    PtySize {
+        rows: rows.min(u16::MAX as usize) as u16,
+        cols: cols.min(u16::MAX as usize) as u16,
+        pixel_width: size.x as u16,
+        pixel_height: size.y as u16,
+    }
+}
 
-            Ok(Box::new(TerminalApp {
-                output_buffer,
-                writer: app_writer,
-                _master_pty: app_master_pty,
-                layout_job: LayoutJob::default(),
-                input_text: String::new(),
-                stick_to_bottom: true,
-                current_format: TextFormat {
-                    font_id: FontId::new(14.0, FontFamily::Monospace),
-                    color: colors.white,
-                    ..Default::default()
-                },
-                partial_char_buffer: Vec::new(),
-                colors,
-            }))
-        }),
+fn visible_position(position: egui::Pos2, size: egui::Vec2, monitor: egui::Vec2) -> egui::Pos2 {
+    egui::pos2(
+        position.x.clamp(0.0, (monitor.x - size.x).max(0.0)),
+        position.y.clamp(0.0, (monitor.y - size.y).max(0.0)),
     )
-    .map_err(|e| anyhow::anyhow!("eframe error: {}", e))?;
+}
+
+fn load_state(path: &Path) -> Result<State> {
+    let toml_string = fs::read_to_string(path).context("Failed to read state.toml file")?;
+    let state = toml::from_str(&toml_string).context("Failed to parse state.toml")?;
+    Ok(state)
+}
 
+fn save_state(path: &Path, state: &State) -> Result<()> {
+    let toml_string = toml::to_string_pretty(state).context("Failed to serialize state to TOML")?;
+    fs::write(path, toml_string).context("Failed to write to state.toml")?;
     Ok(())
 }
+
+#[cfg(target_os = "linux")]
+fn process_name(proc_root: &Path, pid: i32) -> Option<String> {
+    let comm = fs::read_to_string(proc_root.join(pid.to_string()).join("comm")).ok()?;
+    let name = comm.trim_end();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn foreground_process_name(master: &dyn portable_pty::MasterPty) -> Option<String> {
+    let pid = master.process_group_leader()?;
+    process_name(Path::new("/proc"), pid)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn foreground_process_name(_master: &dyn portable_pty::MasterPty) -> Option<String> {
+    None
+}
+
+struct StderrFifo {
+    path: PathBuf,
+    // Keeps the read end from seeing EOF before the child opens the FIFO.
+    _writer: Option<fs::File>,
+}
+
+impl Drop for StderrFifo {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_stderr_fifo() -> Result<(StderrFifo, fs::File)> {
+    use std::{
+        ffi::{CString, OsString},
+        os::{
+            fd::AsRawFd,
+            unix::{ffi::OsStringExt, fs::OpenOptionsExt},
+        },
+    };
+    let template = std::env::temp_dir().join("yate-stderr-XXXXXX");
+    let mut template = CString::new(template.into_os_string().into_vec())?.into_bytes_with_nul();
+    if unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) }.is_null() {
+        return Err(std::io::Error::last_os_error()).context("Failed to create stderr directory");
+    }
+    template.pop();
+    let path = PathBuf::from(OsString::from_vec(template)).join("stderr");
+    let c_path = CString::new(path.clone().into_os_string().into_vec())?;
+    let mut fifo = StderrFifo {
+        path,
+        _writer: None,
+    };
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to create stderr pipe");
+    }
+    let reader = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&fifo.path)?;
+    fifo._writer = Some(fs::OpenOptions::new().write(true).open(&fifo.path)?);
+    unsafe {
+        let flags = libc::fcntl(reader.as_raw_fd(), libc::F_GETFL);
+        libc::fcntl(reader.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+    }
+    Ok((fifo, reader))
+}
+
+#[cfg(not(unix))]
+fn create_stderr_fifo() -> Result<(StderrFifo, fs::File)> {
+    anyhow::bail!("separate stderr capture is only supported on Unix")
+}
+
+fn exit_notice(status: &portable_pty::ExitStatus, respawn: bool) -> String {
+    let mut notice = match status.signal() {
+        Some(signal) => format!("[process killed by {signal}]"),
+        None if status.success() => "[process exited]".to_string(),
+        None => format!("[process exited with code {}]", status.exit_code()),
+    };
+    if respawn {
+        notice.push_str(" Press Enter to restart.");
+    }
+    notice
+}
+
+// Only used for commands given on the command line: an interactive shell prints its prompt and
+// line editing to stderr, so it cannot run with stderr detached from the terminal.
+fn stderr_command(program: &str, args: &[String], fifo: &Path) -> CommandBuilder {
+    let mut cmd = CommandBuilder::new("sh");
+    cmd.args(["-c", "exec \"$0\" \"$@\" 2>\"$YATE_STDERR\"", program]);
+    cmd.args(args);
+    cmd.env("YATE_STDERR", fifo);
+    cmd
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShellMark {
+    PromptStart,
+    PromptEnd,
+    CommandStart,
+    CommandEnd(Option<i32>),
+}
+
+fn parse_shell_mark(argument: &str) -> Option<ShellMark> {
+    let mut parts = argument.split(';');
+    match parts.next()? {
+        "A" => Some(ShellMark::PromptStart),
+        "B" => Some(ShellMark::PromptEnd),
+        "C" => Some(ShellMark::CommandStart),
+        "D" => Some(ShellMark::CommandEnd(
+            parts.next().and_then(|code| code.parse().ok()),
+        )),
+        _ => None,
+    }
+}
+
+fn char_to_byte_index(text: &str, char_index: usize) -> usize {
+    text.char_indices()
+        .nth(char_index)
+        .map_or(text.len(), |(index, _)| index)
+}
+
+fn search(text: &str, query: &str, case_sensitive: bool) -> Vec<Range<usize>> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let Ok(pattern) = regex::RegexBuilder::new(&regex::escape(query))
+        .case_insensitive(!case_sensitive)
+        .build()
+    else {
+        return Vec::new();
+    };
+    pattern.find_iter(text).map(|found| found.range()).collect()
+}
+
+fn step_match(index: Option<usize>, count: usize, forward: bool) -> Option<usize> {
+    if count == 0 {
+        return None;
+    }
+    Some(match (index, forward) {
+        (None, true) => 0,
+        (None, false) => count - 1,
+        (Some(index), true) => (index + 1) % count,
+        (Some(index), false) => (index + count - 1) % count,
+    })
+}
+
+fn compile_patterns(patterns: &[String]) -> Vec<Regex> {
+    patterns
+        .iter()
+        .filter_map(|pattern| {
+            Regex::new(pattern)
+                .inspect_err(|e| eprintln!("Ignoring notify pattern {pattern:?}: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+fn should_notify(line: &str, patterns: &[Regex]) -> bool {
+    patterns.iter().any(|pattern| pattern.is_match(line))
+}
+
+fn collapse_rewrites(text: &str) -> String {
+    text.split('\n')
+        .map(|line| {
+            let line = line.trim_end_matches('\r');
+            line.rsplit('\r').next().unwrap_or(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn command_region_at(regions: &[Range<usize>], position: usize) -> Option<Range<usize>> {
+    regions
+        .iter()
+        .rev()
+        .find(|region| region.start <= position)
+        .cloned()
+}
+
+fn map_offset(offset: usize, range: &Range<usize>, inserted: usize) -> usize {
+    if offset <= range.start {
+        offset
+    } else if offset >= range.end {
+        offset - (range.end - range.start) + inserted
+    } else {
+        range.start + inserted
+    }
+}
+
+fn submission_bytes(line: &str, enters: usize) -> Vec<u8> {
+    if enters == 0 {
+        return Vec::new();
+    }
+    let mut bytes = line.as_bytes().to_vec();
+    bytes.resize(bytes.len() + enters, b'\n');
+    bytes
+}
+
+fn shift_regions(regions: &mut Vec<Range<usize>>, cut: usize) {
+    regions.retain_mut(|region| {
+        region.start = region.start.saturating_sub(cut);
+        region.end = region.end.saturating_sub(cut);
+        region.start < region.end
+    });
+}
+
+#[derive(Clone)]
+struct Cell {
+    ch: char,
+    format: Arc<TextFormat>,
+    bold: bool,
+}
+
+#[derive(Default)]
+struct Line {
+    cells: Vec<Cell>,
+}
+
+impl Line {
+    fn byte_len(&self) -> usize {
+        self.cells.iter().map(|cell| cell.ch.len_utf8()).sum()
+    }
+
+    fn text(&self) -> String {
+        self.cells.iter().map(|cell| cell.ch).collect()
+    }
+}
+
+struct Grid {
+    lines: Vec<Line>,
+    row: usize,
+    column: usize,
+    bytes: usize,
+    chars: usize,
+    format: Arc<TextFormat>,
+    bold: bool,
+    blank: Arc<TextFormat>,
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Self {
+            lines: vec![Line::default()],
+            row: 0,
+            column: 0,
+            bytes: 0,
+            chars: 0,
+            format: Arc::default(),
+            bold: false,
+            blank: Arc::default(),
+        }
+    }
+}
+
+impl Grid {
+    fn len(&self) -> usize {
+        self.bytes
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes == 0
+    }
+
+    fn char_count(&self) -> usize {
+        self.chars
+    }
+
+    fn newline_count(&self) -> usize {
+        self.lines.len() - 1
+    }
+
+    fn screen_top(&self, rows: usize) -> usize {
+        self.lines.len().saturating_sub(rows)
+    }
+
+    fn cursor_overhang(&self) -> usize {
+        self.column.saturating_sub(self.lines[self.row].cells.len())
+    }
+
+    fn at_end(&self) -> bool {
+        self.row + 1 == self.lines.len() && self.column >= self.lines[self.row].cells.len()
+    }
+
+    fn write(&mut self, text: &str, format: TextFormat, bold: bool) {
+        if *self.format != format {
+            self.format = Arc::new(format);
+        }
+        self.bold = bold;
+        for ch in text.chars() {
+            if ch == '\n' {
+                self.newline();
+            } else {
+                self.put(ch);
+            }
+        }
+    }
+
+    fn put(&mut self, ch: char) {
+        let line = &mut self.lines[self.row].cells;
+        while line.len() < self.column {
+            line.push(Cell {
+                ch: ' ',
+                format: self.blank.clone(),
+                bold: false,
+            });
+            self.bytes += 1;
+            self.chars += 1;
+        }
+        let cell = Cell {
+            ch,
+            format: self.format.clone(),
+            bold: self.bold,
+        };
+        match line.get_mut(self.column) {
+            Some(old) => {
+                self.bytes = self.bytes - old.ch.len_utf8() + ch.len_utf8();
+                *old = cell;
+            }
+            None => {
+                self.bytes += ch.len_utf8();
+                self.chars += 1;
+                line.push(cell);
+            }
+        }
+        self.column += 1;
+    }
+
+    fn newline(&mut self) {
+        self.row += 1;
+        self.column = 0;
+        if self.row == self.lines.len() {
+            self.lines.push(Line::default());
+            self.bytes += 1;
+            self.chars += 1;
+        }
+    }
+
+    fn move_cursor(&mut self, row: usize, column: usize, rows: usize, columns: usize) {
+        let row = self.screen_top(rows) + row.min(rows.saturating_sub(1));
+        while self.lines.len() <= row {
+            self.lines.push(Line::default());
+            self.bytes += 1;
+            self.chars += 1;
+        }
+        self.row = row;
+        self.column = column.min(columns.saturating_sub(1));
+    }
+
+    fn scroll_off_screen(&mut self, rows: usize) {
+        self.row = self.lines.len();
+        self.column = 0;
+        self.lines.extend((0..rows.max(1)).map(|_| Line::default()));
+        self.bytes += rows.max(1);
+        self.chars += rows.max(1);
+    }
+
+    fn carriage_return(&mut self) {
+        self.column = 0;
+    }
+
+    fn backspace(&mut self) {
+        self.column = self.column.saturating_sub(1);
+    }
+
+    fn offset(&self, row: usize, column: usize) -> (usize, usize) {
+        let (mut bytes, mut chars) = (self.bytes, self.chars);
+        for line in &self.lines[row + 1..] {
+            bytes -= line.byte_len() + 1;
+            chars -= line.cells.len() + 1;
+        }
+        for cell in self.lines[row].cells.iter().skip(column) {
+            bytes -= cell.ch.len_utf8();
+            chars -= 1;
+        }
+        (bytes, chars)
+    }
+
+    fn cursor_offset(&self) -> (usize, usize) {
+        self.offset(self.row, self.column)
+    }
+
+    fn truncate_line(&mut self, row: usize, column: usize) {
+        let line = &mut self.lines[row].cells;
+        for cell in line.drain(column.min(line.len())..) {
+            self.bytes -= cell.ch.len_utf8();
+            self.chars -= 1;
+        }
+    }
+
+    fn blank_cells(&mut self, row: usize, columns: Range<usize>) {
+        let cells = &mut self.lines[row].cells;
+        for cell in cells.iter_mut().take(columns.end).skip(columns.start) {
+            self.bytes = self.bytes - cell.ch.len_utf8() + 1;
+            *cell = Cell {
+                ch: ' ',
+                format: self.blank.clone(),
+                bold: false,
+            };
+        }
+    }
+
+    fn trim(&mut self, max_lines: usize, max_trim: usize) -> (usize, usize) {
+        let count = self.newline_count().saturating_sub(max_lines).min(max_trim);
+        let (mut bytes, mut chars) = (0, 0);
+        for line in self.lines.drain(..count) {
+            bytes += line.byte_len() + 1;
+            chars += line.cells.len() + 1;
+        }
+        self.bytes -= bytes;
+        self.chars -= chars;
+        if self.row < count {
+            self.column = 0;
+        }
+        self.row = self.row.saturating_sub(count);
+        (bytes, chars)
+    }
+
+    fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(Line::text)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn layout_job(&self, font_id: &FontId) -> LayoutJob {
+        let mut job = LayoutJob::default();
+        let mut text = String::with_capacity(self.bytes);
+        let mut run = (0, &self.blank);
+        let push_run = |sections: &mut Vec<_>, start: usize, end: usize, format: &TextFormat| {
+            if start < end {
+                sections.push(egui::text::LayoutSection {
+                    leading_space: 0.0,
+                    byte_range: start..end,
+                    format: TextFormat {
+                        font_id: font_id.clone(),
+                        ..format.clone()
+                    },
+                });
+            }
+        };
+        for (index, line) in self.lines.iter().enumerate() {
+            if index > 0 {
+                text.push('\n');
+            }
+            for cell in &line.cells {
+                if !Arc::ptr_eq(&cell.format, run.1) && cell.format != *run.1 {
+                    push_run(&mut job.sections, run.0, text.len(), run.1);
+                    run = (text.len(), &cell.format);
+                }
+                text.push(cell.ch);
+            }
+        }
+        push_run(&mut job.sections, run.0, text.len(), run.1);
+        job.text = text;
+        job
+    }
+
+    fn bold_layout_job(&self, font_id: &FontId) -> Option<LayoutJob> {
+        if !self
+            .lines
+            .iter()
+            .any(|line| line.cells.iter().any(|cell| cell.bold))
+        {
+            return None;
+        }
+        let overlay = |cell: &Cell| TextFormat {
+            font_id: font_id.clone(),
+            color: if cell.bold {
+                cell.format.color
+            } else {
+                Color32::TRANSPARENT
+            },
+            background: Color32::TRANSPARENT,
+            underline: egui::Stroke::NONE,
+            strikethrough: egui::Stroke::NONE,
+            ..(*cell.format).clone()
+        };
+        let mut job = LayoutJob::default();
+        let mut run = (0, TextFormat::simple(font_id.clone(), Color32::TRANSPARENT));
+        for (index, line) in self.lines.iter().enumerate() {
+            if index > 0 {
+                job.text.push('\n');
+            }
+            for cell in &line.cells {
+                let format = overlay(cell);
+                if format != run.1 {
+                    let (start, format) = mem::replace(&mut run, (job.text.len(), format));
+                    if start < job.text.len() {
+                        job.sections.push(egui::text::LayoutSection {
+                            leading_space: 0.0,
+                            byte_range: start..job.text.len(),
+                            format,
+                        });
+                    }
+                }
+                job.text.push(cell.ch);
+            }
+        }
+        job.sections.push(egui::text::LayoutSection {
+            leading_space: 0.0,
+            byte_range: run.0..job.text.len(),
+            format: run.1,
+        });
+        Some(job)
+    }
+}
+
+#[derive(Default)]
+struct Screen {
+    grid: Grid,
+    pending_newlines: usize,
+    links: Vec<(Range<usize>, String)>,
+    link_start: Option<(usize, String)>,
+    underline_spans: Vec<(Range<usize>, UnderlineStyle, Color32)>,
+    prompt_regions: Vec<Range<usize>>,
+    prompt_start: Option<usize>,
+    command_regions: Vec<Range<usize>>,
+    command_output_start: Option<usize>,
+}
+
+struct CommandResult {
+    duration: Duration,
+    exit_code: Option<i32>,
+    finished_at: Instant,
+}
+
+fn format_command_result(duration: Duration, exit_code: Option<i32>) -> String {
+    let seconds = duration.as_secs_f64();
+    let elapsed = if seconds < 60.0 {
+        format!("{seconds:.2}s")
+    } else {
+        format!(
+            "{}m{:02}s",
+            duration.as_secs() / 60,
+            duration.as_secs() % 60
+        )
+    };
+    match exit_code {
+        Some(0) => format!("ok · {elapsed}"),
+        Some(code) => format!("exit {code} · {elapsed}"),
+        None => elapsed,
+    }
+}
+
+fn resolve_title(
+    osc_title: Option<&str>,
+    command_title: Option<&str>,
+    process_name: Option<&str>,
+) -> String {
+    osc_title
+        .filter(|title| !title.trim().is_empty())
+        .or(command_title)
+        .or(process_name)
+        .unwrap_or(DEFAULT_TITLE)
+        .to_string()
+}
+
+fn title_from_command_line(line: &str) -> Option<String> {
+    const MAX_TITLE_CHARS: usize = 40;
+    let line = line.trim();
+    let command = line
+        .match_indices(['$', '#', '>', '%'])
+        .find(|&(index, _)| {
+            let rest = &line[index + 1..];
+            (rest.is_empty() || rest.starts_with(' '))
+                && !line[..index].contains(char::is_whitespace)
+        })
+        .map_or(line, |(index, _)| &line[index + 1..]);
+    let mut words = command
+        .split_whitespace()
+        .skip_while(|word| *word == "sudo" || (word.contains('=') && !word.starts_with('=')));
+    let program = words.next()?;
+    let program = program.rsplit('/').next().filter(|name| !name.is_empty())?;
+    let title = match words.next() {
+        Some(argument) => format!("{program} {argument}"),
+        None => program.to_string(),
+    };
+    Some(title.chars().take(MAX_TITLE_CHARS).collect())
+}
+
+fn resolve_profile(saved: Option<&str>, profiles: &BTreeMap<String, ColorPalette>) -> String {
+    match saved {
+        Some(name) if profiles.contains_key(name) => name.to_string(),
+        _ => DEFAULT_PROFILE.to_string(),
+    }
+}
+
+fn load_colors(path: &Path) -> Result<ColorPalette> {
+    let toml_string = fs::read_to_string(path).context("Failed to read colors.toml file")?;
+    let palette = toml::from_str(&toml_string).context("Failed to parse colors.toml")?;
+    Ok(palette)
+}
+
+fn load_colors_or_default(path: &Path, write_default: bool, report_errors: bool) -> Colors {
+    match load_colors(path) {
+        Ok(palette) => Colors::from(palette),
+        Err(e) => {
+            if report_errors {
+                eprintln!("Failed to load colors.toml: {e}. Using default colors.");
+            }
+            let default_colors = Colors::default();
+            if write_default {
+                let _ = save_colors(path, &ColorPalette::from(&default_colors));
+            }
+            default_colors
+        }
+    }
+}
+
+fn import_palette(path: &Path) -> Result<ColorPalette> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let colors = match extension.as_deref() {
+        Some("itermcolors") => parse_itermcolors(&contents),
+        Some("json") => parse_windows_terminal_scheme(&contents),
+        _ => anyhow::bail!("{} is not an .itermcolors or .json file", path.display()),
+    };
+    palette_from_named_colors(&colors)
+        .with_context(|| format!("Failed to import palette from {}", path.display()))
+}
+
+fn parse_itermcolors(plist: &str) -> BTreeMap<String, [u8; 4]> {
+    static ENTRY: OnceLock<Regex> = OnceLock::new();
+    static COMPONENT: OnceLock<Regex> = OnceLock::new();
+    let entry = ENTRY.get_or_init(|| {
+        Regex::new(r"(?s)<key>([^<]+)</key>\s*<dict>(.*?)</dict>").expect("valid regex")
+    });
+    let component = COMPONENT.get_or_init(|| {
+        Regex::new(r"<key>(Red|Green|Blue|Alpha) Component</key>\s*<real>([^<]+)</real>")
+            .expect("valid regex")
+    });
+    let mut colors = BTreeMap::new();
+    for captures in entry.captures_iter(plist) {
+        let name = match captures[1].trim() {
+            "Background Color" => "background".to_string(),
+            "Foreground Color" => "foreground".to_string(),
+            key => match key
+                .strip_prefix("Ansi ")
+                .and_then(|key| key.strip_suffix(" Color"))
+            {
+                Some(index) => format!("ansi{index}"),
+                None => continue,
+            },
+        };
+        let mut rgba = [0, 0, 0, 255];
+        for component in component.captures_iter(&captures[2]) {
+            let Ok(value) = component[2].trim().parse::<f32>() else {
+                continue;
+            };
+            let channel = match &component[1] {
+                "Red" => 0,
+                "Green" => 1,
+                "Blue" => 2,
+                _ => 3,
+            };
+            rgba[channel] = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+        colors.insert(name, rgba);
+    }
+    colors
+}
+
+fn parse_windows_terminal_scheme(json: &str) -> BTreeMap<String, [u8; 4]> {
+    const NAMES: [&str; 16] = [
+        "black",
+        "red",
+        "green",
+        "yellow",
+        "blue",
+        "purple",
+        "cyan",
+        "white",
+        "brightBlack",
+        "brightRed",
+        "brightGreen",
+        "brightYellow",
+        "brightBlue",
+        "brightPurple",
+        "brightCyan",
+        "brightWhite",
+    ];
+    static ENTRY: OnceLock<Regex> = OnceLock::new();
+    let entry = ENTRY
+        .get_or_init(|| Regex::new(r##""(\w+)"\s*:\s*"#([0-9A-Fa-f]{6})""##).expect("valid regex"));
+    let mut colors = BTreeMap::new();
+    for captures in entry.captures_iter(json) {
+        let name = match &captures[1] {
+            "background" | "foreground" => captures[1].to_string(),
+            key => match NAMES.iter().position(|name| *name == key) {
+                Some(index) => format!("ansi{index}"),
+                None => continue,
+            },
+        };
+        let Ok(rgb) = u32::from_str_radix(&captures[2], 16) else {
+            continue;
+        };
+        colors
+            .entry(name)
+            .or_insert([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255]);
+    }
+    colors
+}
+
+fn palette_from_named_colors(colors: &BTreeMap<String, [u8; 4]>) -> Result<ColorPalette> {
+    let color = |name: &str| {
+        colors
+            .get(name)
+            .copied()
+            .with_context(|| format!("Missing {name} color"))
+    };
+    Ok(ColorPalette {
+        background: color("background")?,
+        white: color("foreground").or_else(|_| color("ansi7"))?,
+        black: color("ansi0")?,
+        red: color("ansi1")?,
+        green: color("ansi2")?,
+        yellow: color("ansi3")?,
+        blue: color("ansi4")?,
+        magenta: color("ansi5")?,
+        cyan: color("ansi6")?,
+        grey: color("ansi8")?,
+        bright_red: color("ansi9")?,
+        bright_green: color("ansi10")?,
+        bright_yellow: color("ansi11")?,
+        bright_blue: color("ansi12")?,
+        bright_magenta: color("ansi13")?,
+        bright_cyan: color("ansi14")?,
+    })
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn save_colors(path: &Path, colors: &ColorPalette) -> Result<()> {
+    save_palette(path, None, colors)
+}
+
+fn save_palette(path: &Path, profile: Option<&str>, colors: &ColorPalette) -> Result<()> {
+    let mut document = match fs::read_to_string(path) {
+        Ok(toml_string) => toml::from_str(&toml_string).context("Failed to parse colors.toml")?,
+        Err(_) => toml::Table::new(),
+    };
+    let palette = toml::Table::try_from(colors).context("Failed to serialize colors to TOML")?;
+    match profile {
+        Some(name) => {
+            let profiles = document
+                .entry("profiles")
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if let toml::Value::Table(profiles) = profiles {
+                profiles.insert(name.to_string(), toml::Value::Table(palette));
+            }
+        }
+        None => document.extend(palette),
+    }
+    let toml_string =
+        toml::to_string_pretty(&document).context("Failed to serialize colors to TOML")?;
+    fs::write(path, toml_string).context("Failed to write to colors.toml")?;
+    Ok(())
+}
+
+fn composite_over(top: Color32, base: Color32) -> Color32 {
+    let [top_r, top_g, top_b, top_a] = top.to_srgba_unmultiplied();
+    let [base_r, base_g, base_b, base_a] = base.to_srgba_unmultiplied();
+    let top_alpha = top_a as f32 / 255.0;
+    let base_alpha = base_a as f32 / 255.0 * (1.0 - top_alpha);
+    let alpha = top_alpha + base_alpha;
+    if alpha <= 0.0 {
+        return Color32::TRANSPARENT;
+    }
+    let channel = |top: u8, base: u8| {
+        ((top as f32 * top_alpha + base as f32 * base_alpha) / alpha).round() as u8
+    };
+    Color32::from_rgba_unmultiplied(
+        channel(top_r, base_r),
+        channel(top_g, base_g),
+        channel(top_b, base_b),
+        (alpha * 255.0).round() as u8,
+    )
+}
+
+fn parse_osc_color(spec: &str) -> Option<Color32> {
+    if let Some(hex) = spec.strip_prefix('#') {
+        if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+        return Some(Color32::from_rgb(channel(0)?, channel(2)?, channel(4)?));
+    }
+    let mut channels = spec.strip_prefix("rgb:")?.split('/').map(|channel| {
+        if !(1..=4).contains(&channel.len())
+            || !channel.bytes().all(|byte| byte.is_ascii_hexdigit())
+        {
+            return None;
+        }
+        let value = u64::from_str_radix(channel, 16).ok()?;
+        let max = 16u64.checked_pow(channel.len() as u32)?.checked_sub(1)?;
+        (max > 0).then(|| (value * 255 / max) as u8)
+    });
+    let (r, g, b) = (channels.next()??, channels.next()??, channels.next()??);
+    channels
+        .next()
+        .is_none()
+        .then(|| Color32::from_rgb(r, g, b))
+}
+
+fn should_save_palette(dirty_since: Option<Instant>, now: Instant, debounce: Duration) -> bool {
+    dirty_since.is_some_and(|since| now.duration_since(since) >= debounce)
+}
+
+impl Colors {
+    fn slot_mut(&mut self, index: usize) -> Option<&mut Color32> {
+        Some(match index {
+            0 => &mut self.black,
+            1 => &mut self.red,
+            2 => &mut self.green,
+            3 => &mut self.yellow,
+            4 => &mut self.blue,
+            5 => &mut self.magenta,
+            6 => &mut self.cyan,
+            7 | 15 => &mut self.white,
+            8 => &mut self.grey,
+            9 => &mut self.bright_red,
+            10 => &mut self.bright_green,
+            11 => &mut self.bright_yellow,
+            12 => &mut self.bright_blue,
+            13 => &mut self.bright_magenta,
+            14 => &mut self.bright_cyan,
+            _ => return None,
+        })
+    }
+
+    fn slot(&self, index: usize) -> Option<Color32> {
+        Some(match index {
+            0 => self.black,
+            1 => self.red,
+            2 => self.green,
+            3 => self.yellow,
+            4 => self.blue,
+            5 => self.magenta,
+            6 => self.cyan,
+            7 | 15 => self.white,
+            8 => self.grey,
+            9 => self.bright_red,
+            10 => self.bright_green,
+            11 => self.bright_yellow,
+            12 => self.bright_blue,
+            13 => self.bright_magenta,
+            14 => self.bright_cyan,
+            _ => return None,
+        })
+    }
+
+    fn named(&self) -> [(&'static str, Color32); 15] {
+        [
+            ("white", self.white),
+            ("black", self.black),
+            ("red", self.red),
+            ("green", self.green),
+            ("yellow", self.yellow),
+            ("blue", self.blue),
+            ("magenta", self.magenta),
+            ("cyan", self.cyan),
+            ("grey", self.grey),
+            ("bright_red", self.bright_red),
+            ("bright_green", self.bright_green),
+            ("bright_yellow", self.bright_yellow),
+            ("bright_blue", self.bright_blue),
+            ("bright_magenta", self.bright_magenta),
+            ("bright_cyan", self.bright_cyan),
+        ]
+    }
+}
+
+fn color_256(colors: &Colors, index: u8) -> Color32 {
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match index {
+        0..=15 => colors.slot(index as usize).unwrap_or(colors.white),
+        16..=231 => {
+            let cube = index - 16;
+            Color32::from_rgb(
+                CUBE_LEVELS[(cube / 36) as usize],
+                CUBE_LEVELS[(cube / 6 % 6) as usize],
+                CUBE_LEVELS[(cube % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + 10 * (index - 232);
+            Color32::from_rgb(level, level, level)
+        }
+    }
+}
+
+fn extended_color(params: &mut impl Iterator<Item = u32>, colors: &Colors) -> Option<Color32> {
+    let channel = |value: Option<u32>| value.map(|value| value.min(255) as u8);
+    match params.next()? {
+        5 => Some(color_256(colors, channel(params.next())?)),
+        2 => Some(Color32::from_rgb(
+            channel(params.next())?,
+            channel(params.next())?,
+            channel(params.next())?,
+        )),
+        _ => None,
+    }
+}
+
+fn palette_color_name(colors: &Colors, color: Color32) -> Option<&'static str> {
+    colors
+        .named()
+        .into_iter()
+        .find(|(_, entry)| *entry == color)
+        .map(|(name, _)| name)
+}
+
+struct Colors {
+    background: Color32,
+    white: Color32,
+    black: Color32,
+    red: Color32,
+    green: Color32,
+    yellow: Color32,
+    blue: Color32,
+    magenta: Color32,
+    cyan: Color32,
+    grey: Color32,
+    bright_red: Color32,
+    bright_green: Color32,
+    bright_yellow: Color32,
+    bright_blue: Color32,
+    bright_magenta: Color32,
+    bright_cyan: Color32,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            background: Color32::from_rgb(20, 20, 20),
+            white: Color32::WHITE,
+            black: Color32::BLACK,
+            red: Color32::RED,
+            green: Color32::GREEN,
+            yellow: Color32::YELLOW,
+            blue: Color32::BLUE,
+            magenta: Color32::MAGENTA,
+            cyan: Color32::CYAN,
+            grey: Color32::GRAY,
+            bright_red: Color32::LIGHT_RED,
+            bright_green: Color32::LIGHT_GREEN,
+            bright_yellow: Color32::LIGHT_YELLOW,
+            bright_blue: Color32::LIGHT_BLUE,
+            bright_magenta: Color32::PURPLE,
+            bright_cyan: Color32::DARK_BLUE,
+        }
+    }
+}
+
+#[derive(Default)]
+struct OscParser {
+    payload: Vec<u8>,
+    escape: bool,
+    overflowed: bool,
+}
+
+impl OscParser {
+    fn push(&mut self, byte: u8, max_payload: usize) -> bool {
+        match byte {
+            0x07 => return true,
+            b'\\' if self.escape => return true,
+            0x1b => self.escape = true,
+            _ if self.escape => return true,
+            _ if self.overflowed => {}
+            _ if self.payload.len() >= max_payload => {
+                self.overflowed = true;
+                self.payload = Vec::new();
+            }
+            _ => self.payload.push(byte),
+        }
+        false
+    }
+}
+
+fn decrqss_reply(query: &str, sgr: &str, screen_rows: usize) -> String {
+    let setting = match query {
+        "m" => Some(format!("{sgr}m")),
+        "r" => Some(format!("1;{screen_rows}r")),
+        " q" => Some("2 q".to_string()),
+        _ => None,
+    };
+    match setting {
+        Some(setting) => format!("\x1bP1$r{setting}\x1b\\"),
+        None => "\x1bP0$r\x1b\\".to_string(),
+    }
+}
+
+fn window_size_report(operation: u32, rows: usize, cols: usize) -> Option<String> {
+    match operation {
+        18 => Some(format!("\x1b[8;{rows};{cols}t")),
+        19 => Some(format!("\x1b[9;{rows};{cols}t")),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TerminalModes {
+    application_cursor: bool,
+    application_keypad: bool,
+    autowrap: bool,
+    cursor_visible: bool,
+    bracketed_paste: bool,
+    mouse_reporting: bool,
+    focus_reporting: bool,
+    alt_screen: bool,
+    kitty_keyboard_flags: u32,
+}
+
+impl TerminalModes {
+    fn new(autowrap: bool) -> Self {
+        Self {
+            application_cursor: false,
+            application_keypad: false,
+            autowrap,
+            cursor_visible: true,
+            bracketed_paste: false,
+            mouse_reporting: false,
+            focus_reporting: false,
+            alt_screen: false,
+            kitty_keyboard_flags: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KittyKeyboardRequest {
+    Push(u32),
+    Pop(usize),
+    Set(u32, u32),
+    Query,
+}
+
+fn cursor_target(
+    final_byte: &str,
+    params: &str,
+    row: usize,
+    column: usize,
+) -> Option<(usize, usize)> {
+    let mut numbers = params
+        .split(';')
+        .map(|part| part.parse::<usize>().unwrap_or(0).max(1));
+    let mut next = || numbers.next().unwrap_or(1);
+    match final_byte {
+        "A" => Some((row.saturating_sub(next()), column)),
+        "B" => Some((row.saturating_add(next()), column)),
+        "C" => Some((row, column.saturating_add(next()))),
+        "D" => Some((row, column.saturating_sub(next()))),
+        "G" => Some((row, next() - 1)),
+        "H" | "f" => Some((next() - 1, next() - 1)),
+        _ => None,
+    }
+}
+
+fn parse_kitty_keyboard(params: &str) -> Option<KittyKeyboardRequest> {
+    let number = |text: &str| text.parse::<u32>().ok();
+    if params == "?" {
+        Some(KittyKeyboardRequest::Query)
+    } else if let Some(flags) = params.strip_prefix('>') {
+        Some(KittyKeyboardRequest::Push(number(flags).unwrap_or(0)))
+    } else if let Some(count) = params.strip_prefix('<') {
+        Some(KittyKeyboardRequest::Pop(
+            number(count).unwrap_or(1).max(1) as usize
+        ))
+    } else if let Some(rest) = params.strip_prefix('=') {
+        let (flags, mode) = rest.split_once(';').unwrap_or((rest, "1"));
+        Some(KittyKeyboardRequest::Set(
+            number(flags).unwrap_or(0),
+            number(mode).unwrap_or(1),
+        ))
+    } else {
+        None
+    }
+}
+
+struct Session {
+    shell: Option<ShellProcess>,
+    screen: Screen,
+    primary_screen: Option<Screen>,
+    galley: Option<Arc<Galley>>,
+    bold_galley: Option<Arc<Galley>>,
+    layout_dirty: bool,
+    output_changed: bool,
+    modes: TerminalModes,
+    pty_size: PtySize,
+    screen_rows: usize,
+    screen_cols: usize,
+    current_format: TextFormat,
+    bold: bool,
+    faint: bool,
+    strikethrough: bool,
+    underline_style: UnderlineStyle,
+    partial_char_buffer: Vec<u8>,
+    utf8_tail: Vec<u8>,
+    osc: Option<OscParser>,
+    dcs: Option<OscParser>,
+    synchronized_output: Option<Instant>,
+    osc_title: Option<String>,
+    command_title: Option<String>,
+    process_name: Option<String>,
+    title_stack: Vec<Option<String>>,
+    kitty_keyboard_stack: Vec<u32>,
+    raw_output: VecDeque<u8>,
+    raw_output_offset: usize,
+    hex_dump_text: Option<String>,
+    prompt_flash: Option<(Instant, usize)>,
+    pager_target: Option<usize>,
+    stick_to_bottom: bool,
+    horizontal_offset: f32,
+    scroll_offset_y: f32,
+    scroll_velocity: f32,
+    content_width: f32,
+    content_height: f32,
+    pager: bool,
+    scroll_pages: i32,
+    scroll_to_top: bool,
+    search_query: Option<String>,
+    search_target: Option<usize>,
+    search_matches: Vec<Range<usize>>,
+    search_index: Option<usize>,
+    search_dirty: bool,
+    bell_flash: Option<Instant>,
+    unseen_bell: bool,
+    command_started_at: Option<Instant>,
+    last_command: Option<CommandResult>,
+    notify_patterns: Vec<Regex>,
+    notify_line: String,
+    input_text: String,
+    control_chord: Option<(Instant, String)>,
+    scrollback_lines: usize,
+    replies: Vec<u8>,
+    bell: bool,
+    notification: Option<String>,
+    palette_changed: bool,
+}
+
+impl Session {
+    fn new(config: &Config, colors: &Colors, font_size: f32, pty_size: PtySize) -> Self {
+        Self {
+            shell: None,
+            screen: Screen::default(),
+            primary_screen: None,
+            galley: None,
+            bold_galley: None,
+            layout_dirty: false,
+            output_changed: false,
+            modes: TerminalModes::new(config.wrap_lines),
+            pty_size,
+            screen_rows: pty_size.rows as usize,
+            screen_cols: pty_size.cols as usize,
+            current_format: TextFormat {
+                font_id: FontId::new(font_size, FontFamily::Monospace),
+                color: colors.white,
+                ..Default::default()
+            },
+            bold: false,
+            faint: false,
+            strikethrough: false,
+            underline_style: UnderlineStyle::None,
+            partial_char_buffer: Vec::new(),
+            utf8_tail: Vec::new(),
+            osc: None,
+            dcs: None,
+            synchronized_output: None,
+            osc_title: None,
+            command_title: None,
+            process_name: None,
+            title_stack: Vec::new(),
+            kitty_keyboard_stack: Vec::new(),
+            raw_output: VecDeque::new(),
+            raw_output_offset: 0,
+            hex_dump_text: None,
+            prompt_flash: None,
+            pager_target: None,
+            stick_to_bottom: true,
+            horizontal_offset: 0.0,
+            scroll_offset_y: 0.0,
+            scroll_velocity: 0.0,
+            content_width: 0.0,
+            content_height: 0.0,
+            pager: false,
+            scroll_pages: 0,
+            scroll_to_top: false,
+            search_query: None,
+            search_target: None,
+            search_matches: Vec::new(),
+            search_index: None,
+            search_dirty: false,
+            bell_flash: None,
+            unseen_bell: false,
+            command_started_at: None,
+            last_command: None,
+            notify_patterns: compile_patterns(&config.notify_patterns),
+            notify_line: String::new(),
+            input_text: String::new(),
+            control_chord: None,
+            scrollback_lines: tab_scrollback_lines(config, 0),
+            replies: Vec::new(),
+            bell: false,
+            notification: None,
+            palette_changed: false,
+        }
+    }
+
+    fn write_to_pty(&mut self, bytes: &[u8]) {
+        if let Some(shell) = &mut self.shell {
+            let _ = shell.writer.write_all(bytes);
+            let _ = shell.writer.flush();
+        }
+    }
+
+    fn send_input(&mut self, bytes: &[u8], config: &Config) {
+        if config.show_control_chords
+            && let [byte] = bytes[..]
+            && let Some(chord) = caret_notation(byte)
+        {
+            self.control_chord = Some((Instant::now(), chord));
+        }
+        self.write_to_pty(bytes);
+    }
+
+    fn resize_pty(&mut self, size: PtySize) {
+        if (size.rows, size.cols) == (self.pty_size.rows, self.pty_size.cols) {
+            return;
+        }
+        if let Some(shell) = &self.shell
+            && let Err(e) = shell.master.resize(size)
+        {
+            eprintln!("Failed to resize PTY: {e}");
+        }
+        self.pty_size = size;
+    }
+
+    fn record_raw_output(&mut self, bytes: &[u8], max_bytes: usize) {
+        if bytes.is_empty() {
+            return;
+        }
+        self.raw_output.extend(bytes);
+        let excess = self.raw_output.len().saturating_sub(max_bytes);
+        self.raw_output.drain(..excess);
+        self.raw_output_offset += excess;
+        self.hex_dump_text = None;
+    }
+
+    fn handle_osc(&mut self, payload: &str, config: &Config, colors: &mut Colors) {
+        let (command, argument) = payload.split_once(';').unwrap_or((payload, ""));
+        match command {
+            "0" | "2" => self.osc_title = Some(argument.to_string()),
+            "4" => {
+                let mut parts = argument.split(';');
+                while let (Some(index), Some(spec)) = (parts.next(), parts.next()) {
+                    if let Ok(index) = index.parse()
+                        && let Some(color) = parse_osc_color(spec)
+                        && let Some(slot) = colors.slot_mut(index)
+                    {
+                        *slot = color;
+                        self.palette_changed = true;
+                    }
+                }
+            }
+            "10" => {
+                if let Some(color) = parse_osc_color(argument) {
+                    colors.white = color;
+                    self.current_format.color = color;
+                    self.palette_changed = true;
+                }
+            }
+            "11" => {
+                if let Some(color) = parse_osc_color(argument) {
+                    colors.background = color;
+                    self.palette_changed = true;
+                }
+            }
+            "8" if config.osc8_links => {
+                let (position, _) = self.cursor_position();
+                if let Some((start, url)) = self.screen.link_start.take()
+                    && start < position
+                {
+                    self.screen.links.push((start..position, url));
+                }
+                let (_, uri) = argument.split_once(';').unwrap_or(("", argument));
+                if !uri.is_empty() {
+                    self.screen.link_start = Some((position, uri.to_string()));
+                }
+            }
+            "133" => match parse_shell_mark(argument) {
+                Some(ShellMark::PromptStart) => {
+                    self.command_title = None;
+                    let (_, char_index) = self.cursor_position();
+                    self.screen.prompt_start = Some(char_index);
+                    if config.flash_prompt {
+                        self.prompt_flash = Some((Instant::now(), char_index));
+                    }
+                }
+                Some(ShellMark::PromptEnd) => {
+                    if let Some(start) = self.screen.prompt_start.take() {
+                        let end = self.cursor_position().1.max(start);
+                        self.screen.prompt_regions.push(start..end);
+                    }
+                }
+                Some(ShellMark::CommandStart) => {
+                    if let Some(start) = self.screen.prompt_start.take() {
+                        let end = self.cursor_position().1.max(start);
+                        self.screen.prompt_regions.push(start..end);
+                    }
+                    self.command_started_at = Some(Instant::now());
+                    self.screen.command_output_start = Some(self.cursor_position().1);
+                }
+                Some(ShellMark::CommandEnd(exit_code)) => {
+                    if let Some(start) = self.screen.command_output_start.take() {
+                        let end = self.cursor_position().1.max(start);
+                        self.screen.command_regions.push(start..end);
+                    }
+                    if let Some(started_at) = self.command_started_at.take() {
+                        self.last_command = Some(CommandResult {
+                            duration: started_at.elapsed(),
+                            exit_code,
+                            finished_at: Instant::now(),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn cursor_position(&self) -> (usize, usize) {
+        let (bytes, chars) = self.screen.grid.cursor_offset();
+        let padding = self.screen.grid.cursor_overhang() + self.screen.pending_newlines;
+        (bytes + padding, chars + padding)
+    }
+
+    fn title(&self) -> String {
+        resolve_title(
+            self.osc_title.as_deref(),
+            self.command_title.as_deref(),
+            self.process_name.as_deref(),
+        )
+    }
+
+    fn append_text(&mut self, bytes: &[u8], config: &Config, colors: &Colors) {
+        let mut text = replace_unprintable(&String::from_utf8_lossy(bytes), config.unprintable);
+        if text.is_empty() {
+            return;
+        }
+        if text.contains('\t') {
+            text = expand_tabs(&text, self.screen.grid.column, config.tab_width);
+        }
+        self.output_changed = true;
+        if !self.notify_patterns.is_empty() {
+            self.check_notify_patterns(&text);
+        }
+        let format = self.effective_format(config, colors);
+        if config.underline_styles
+            && self.screen.grid.at_end()
+            && !matches!(
+                self.underline_style,
+                UnderlineStyle::None | UnderlineStyle::Straight
+            )
+        {
+            let start = self.screen.grid.char_count();
+            let end = start + text.chars().count();
+            match self.screen.underline_spans.last_mut() {
+                Some((range, style, color))
+                    if range.end == start
+                        && *style == self.underline_style
+                        && *color == format.color =>
+                {
+                    range.end = end;
+                }
+                _ => self.screen.underline_spans.push((
+                    start..end,
+                    self.underline_style,
+                    format.color,
+                )),
+            }
+        }
+        self.screen.grid.write(&text, format, self.bold);
+    }
+
+    fn flush_pending_newlines(&mut self, config: &Config, colors: &Colors) {
+        if self.screen.pending_newlines > 0 {
+            let newlines = "\n".repeat(mem::take(&mut self.screen.pending_newlines));
+            self.append_text(newlines.as_bytes(), config, colors);
+        }
+    }
+
+    fn trim_scrollback(&mut self, max_trim: usize) -> bool {
+        self.drop_old_lines(self.scrollback_lines, max_trim.max(1));
+        self.screen.grid.newline_count() > self.scrollback_lines
+    }
+
+    fn clear_scrollback(&mut self) {
+        self.drop_old_lines(self.screen_rows.saturating_sub(1), usize::MAX);
+    }
+
+    fn drop_old_lines(&mut self, max_lines: usize, max_trim: usize) {
+        let (cut, removed_chars) = self.screen.grid.trim(max_lines, max_trim);
+        if cut == 0 {
+            return;
+        }
+        self.pager_target = self
+            .pager_target
+            .map(|position| position.saturating_sub(cut));
+        shift_regions(&mut self.screen.prompt_regions, removed_chars);
+        self.screen.underline_spans.retain_mut(|(range, _, _)| {
+            range.start = range.start.saturating_sub(removed_chars);
+            range.end = range.end.saturating_sub(removed_chars);
+            range.start < range.end
+        });
+        self.screen.prompt_start = None;
+        shift_regions(&mut self.screen.command_regions, removed_chars);
+        self.screen.links.retain_mut(|(range, _)| {
+            range.start = range.start.saturating_sub(cut);
+            range.end = range.end.saturating_sub(cut);
+            range.start < range.end
+        });
+        self.screen.link_start = self
+            .screen
+            .link_start
+            .take()
+            .map(|(start, url)| (start.saturating_sub(cut), url));
+        self.screen.command_output_start = self
+            .screen
+            .command_output_start
+            .map(|start| start.saturating_sub(removed_chars));
+        self.prompt_flash = None;
+        self.layout_dirty = true;
+    }
+
+    fn append_stderr(&mut self, bytes: &[u8], config: &Config, colors: &Colors) {
+        let text = strip_ansi(&String::from_utf8_lossy(bytes));
+        let stderr_color = config.stderr_color.map_or(colors.red, |[r, g, b, a]| {
+            Color32::from_rgba_unmultiplied(r, g, b, a)
+        });
+        let saved_format = self.current_format.clone();
+        let saved_bold = mem::take(&mut self.bold);
+        self.current_format.color = stderr_color;
+        let newlines = "\n".repeat(mem::take(&mut self.screen.pending_newlines));
+        self.append_text(format!("{newlines}{text}").as_bytes(), config, colors);
+        self.current_format = saved_format;
+        self.bold = saved_bold;
+    }
+
+    fn show_exit_notice(&mut self, notice: &str, config: &Config, colors: &Colors) {
+        self.set_alt_screen(false);
+        self.reset_sgr(colors);
+        let newline = if self.screen.pending_newlines == 0
+            && self.screen.grid.lines[self.screen.grid.row]
+                .cells
+                .is_empty()
+        {
+            ""
+        } else {
+            "\n"
+        };
+        let newlines = "\n".repeat(mem::take(&mut self.screen.pending_newlines));
+        self.faint = true;
+        self.append_text(
+            format!("{newlines}{newline}{notice}").as_bytes(),
+            config,
+            colors,
+        );
+        self.faint = false;
+        self.layout_dirty = true;
+    }
+
+    fn check_notify_patterns(&mut self, text: &str) {
+        let mut lines = text.split('\n');
+        if let Some(first) = lines.next() {
+            self.notify_line.push_str(first);
+        }
+        for next in lines {
+            let line = mem::replace(&mut self.notify_line, next.to_string());
+            if should_notify(&line, &self.notify_patterns) {
+                self.notification = Some(line.trim().to_string());
+            }
+        }
+    }
+
+    fn effective_format(&self, config: &Config, colors: &Colors) -> TextFormat {
+        let mut format = self.current_format.clone();
+        if self.faint {
+            let background = if format.background == Color32::TRANSPARENT {
+                colors.background
+            } else {
+                format.background
+            };
+            format.color = format.color.lerp_to_gamma(background, FAINT_BLEND);
+        }
+        if self.strikethrough {
+            format.strikethrough = egui::Stroke::new(1.0, format.color);
+        }
+        let straight = match self.underline_style {
+            UnderlineStyle::None => false,
+            UnderlineStyle::Straight => true,
+            _ => !config.underline_styles,
+        };
+        if straight {
+            format.underline = egui::Stroke::new(1.0, format.color);
+        }
+        format
+    }
+
+    fn primary_grid(&self) -> &Grid {
+        self.primary_screen
+            .as_ref()
+            .map_or(&self.screen.grid, |screen| &screen.grid)
+    }
+
+    fn scrollback_to_plain(&self) -> String {
+        self.primary_grid().text()
+    }
+
+    fn scrollback_to_sgr(&self) -> String {
+        let grid = self.primary_grid();
+        let mut output = String::with_capacity(grid.bytes);
+        let mut style = None;
+        for (index, line) in grid.lines.iter().enumerate() {
+            if index > 0 {
+                output.push('\n');
+            }
+            for cell in &line.cells {
+                if style != Some((&*cell.format, cell.bold)) {
+                    output.push_str(&sgr_for_format(&cell.format, cell.bold));
+                    style = Some((&*cell.format, cell.bold));
+                }
+                output.push(cell.ch);
+            }
+        }
+        output.push_str("\x1b[0m");
+        output
+    }
+
+    fn handle_csi(&mut self, sequence: &str, config: &Config, colors: &Colors) {
+        let (params, final_byte) = sequence.split_at(sequence.len() - 1);
+        if final_byte == "u"
+            && config.kitty_keyboard
+            && let Some(request) = parse_kitty_keyboard(params)
+        {
+            self.handle_kitty_keyboard(request);
+            return;
+        }
+        if let Some(modes) = params.strip_prefix('?') {
+            if matches!(final_byte, "h" | "l") {
+                for mode in modes.split(';').filter_map(|part| part.parse::<u32>().ok()) {
+                    self.set_private_mode(mode, final_byte == "h", config);
+                }
+            }
+            return;
+        }
+        match final_byte {
+            "m" => self.handle_sgr(params, colors),
+            "J" if matches!(params, "" | "0" | "1") => {
+                self.erase_in_display(params, config, colors)
+            }
+            "J" if params == "2" => self.clear_screen(config),
+            "K" => self.erase_in_line(params),
+            "A" | "B" | "C" | "D" | "G" | "H" | "f" => {
+                self.move_cursor(final_byte, params, config, colors)
+            }
+            "J" if params == "3" && config.clear_scrollback => self.clear_scrollback(),
+            "p" if params == "!" => self.soft_reset(config, colors),
+            "t" => self.handle_window_op(params, config),
+            _ => {}
+        }
+    }
+
+    fn move_cursor(&mut self, final_byte: &str, params: &str, config: &Config, colors: &Colors) {
+        self.flush_pending_newlines(config, colors);
+        let top = self.screen.grid.screen_top(self.screen_rows);
+        if let Some((row, column)) = cursor_target(
+            final_byte,
+            params,
+            self.screen.grid.row.saturating_sub(top),
+            self.screen.grid.column,
+        ) {
+            self.screen
+                .grid
+                .move_cursor(row, column, self.screen_rows, self.screen_cols);
+            self.output_changed = true;
+        }
+    }
+
+    fn handle_kitty_keyboard(&mut self, request: KittyKeyboardRequest) {
+        let flags = &mut self.modes.kitty_keyboard_flags;
+        match request {
+            KittyKeyboardRequest::Push(new_flags) => {
+                self.kitty_keyboard_stack.push(*flags);
+                *flags = new_flags;
+            }
+            KittyKeyboardRequest::Pop(count) => {
+                let keep = self.kitty_keyboard_stack.len().saturating_sub(count);
+                *flags = if count > self.kitty_keyboard_stack.len() {
+                    0
+                } else {
+                    self.kitty_keyboard_stack[keep]
+                };
+                self.kitty_keyboard_stack.truncate(keep);
+            }
+            KittyKeyboardRequest::Set(new_flags, mode) => match mode {
+                2 => *flags |= new_flags,
+                3 => *flags &= !new_flags,
+                _ => *flags = new_flags,
+            },
+            KittyKeyboardRequest::Query => {
+                let reply = format!("\x1b[?{flags}u");
+                self.replies.extend_from_slice(reply.as_bytes());
+            }
+        }
+    }
+
+    fn handle_window_op(&mut self, params: &str, config: &Config) {
+        let mut params = params
+            .split(';')
+            .map(|part| part.parse::<u32>().unwrap_or(0));
+        let operation = params.next().unwrap_or(0);
+        let window_title = matches!(params.next().unwrap_or(0), 0 | 2);
+        if config.title_stack && window_title {
+            match operation {
+                22 => {
+                    if self.title_stack.len() == TITLE_STACK_LIMIT {
+                        self.title_stack.remove(0);
+                    }
+                    self.title_stack.push(self.osc_title.clone());
+                }
+                23 => {
+                    if let Some(title) = self.title_stack.pop() {
+                        self.osc_title = title;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if config.report_window_size
+            && let Some(reply) = window_size_report(operation, self.screen_rows, self.screen_cols)
+        {
+            self.replies.extend_from_slice(reply.as_bytes());
+        }
+    }
+
+    fn handle_sgr(&mut self, params: &str, colors: &Colors) {
+        let mut parts = params.split(';');
+        while let Some(part) = parts.next() {
+            if let Some(style) = part.strip_prefix("4:") {
+                self.underline_style = parse_underline_style(style);
+                continue;
+            }
+            let mut numbers = parts.by_ref().map(|part| part.parse::<u32>().unwrap_or(0));
+            match part.parse::<u32>().unwrap_or(0) {
+                0 => self.reset_sgr(colors),
+                38 => {
+                    if let Some(color) = extended_color(&mut numbers, colors) {
+                        self.current_format.color = color;
+                    }
+                }
+                48 => {
+                    if let Some(color) = extended_color(&mut numbers, colors) {
+                        self.current_format.background = color;
+                    }
+                }
+                1 => self.bold = true,
+                2 => self.faint = true,
+                3 => self.current_format.italics = true,
+                4 => self.underline_style = UnderlineStyle::Straight,
+                21 => self.underline_style = UnderlineStyle::Double,
+                9 => self.strikethrough = true,
+                22 => {
+                    self.bold = false;
+                    self.faint = false;
+                }
+                23 => self.current_format.italics = false,
+                24 => self.underline_style = UnderlineStyle::None,
+                29 => self.strikethrough = false,
+                30 => self.current_format.color = colors.black,
+                31 => self.current_format.color = colors.red,
+                32 => self.current_format.color = colors.green,
+                33 => self.current_format.color = colors.yellow,
+                34 => self.current_format.color = colors.blue,
+                35 => self.current_format.color = colors.magenta,
+                36 => self.current_format.color = colors.cyan,
+                37 => self.current_format.color = colors.white,
+                39 => self.current_format.color = colors.white,
+                90 => self.current_format.color = colors.grey,
+                91 => self.current_format.color = colors.bright_red,
+                92 => self.current_format.color = colors.bright_green,
+                93 => self.current_format.color = colors.bright_yellow,
+                94 => self.current_format.color = colors.bright_blue,
+                95 => self.current_format.color = colors.bright_magenta,
+                96 => self.current_format.color = colors.bright_cyan,
+                97 => self.current_format.color = colors.white,
+                code @ 40..=47 => {
+                    if let Some(color) = colors.slot(code as usize - 40) {
+                        self.current_format.background = color;
+                    }
+                }
+                49 => self.current_format.background = Color32::TRANSPARENT,
+                code @ 100..=107 => {
+                    if let Some(color) = colors.slot(code as usize - 92) {
+                        self.current_format.background = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn current_sgr(&self, colors: &Colors) -> String {
+        let mut params = vec!["0".to_string()];
+        if self.bold {
+            params.push("1".to_string());
+        }
+        if self.faint {
+            params.push("2".to_string());
+        }
+        if self.current_format.italics {
+            params.push("3".to_string());
+        }
+        if self.underline_style != UnderlineStyle::None {
+            params.push("4".to_string());
+        }
+        if self.strikethrough {
+            params.push("9".to_string());
+        }
+        let palette = [
+            (colors.black, 30),
+            (colors.red, 31),
+            (colors.green, 32),
+            (colors.yellow, 33),
+            (colors.blue, 34),
+            (colors.magenta, 35),
+            (colors.cyan, 36),
+            (colors.grey, 90),
+            (colors.bright_red, 91),
+            (colors.bright_green, 92),
+            (colors.bright_yellow, 93),
+            (colors.bright_blue, 94),
+            (colors.bright_magenta, 95),
+            (colors.bright_cyan, 96),
+        ];
+        if self.current_format.color != colors.white
+            && let Some((_, code)) = palette
+                .iter()
+                .find(|(color, _)| *color == self.current_format.color)
+        {
+            params.push(code.to_string());
+        }
+        if self.current_format.background != Color32::TRANSPARENT
+            && let Some((_, code)) = palette
+                .iter()
+                .find(|(color, _)| *color == self.current_format.background)
+        {
+            params.push((code + 10).to_string());
+        }
+        params.join(";")
+    }
+
+    fn handle_dcs(&mut self, payload: &str, config: &Config, colors: &Colors) {
+        if let Some(query) = payload.strip_prefix("$q")
+            && config.answer_decrqss
+        {
+            let reply = decrqss_reply(query, &self.current_sgr(colors), self.screen_rows);
+            self.replies.extend_from_slice(reply.as_bytes());
+        }
+    }
+
+    fn clear_screen(&mut self, config: &Config) {
+        self.screen.pending_newlines = 0;
+        self.output_changed = true;
+        match config.clear_mode {
+            ClearMode::Truncate => {
+                self.screen.grid = Grid::default();
+                self.screen.command_regions.clear();
+                self.screen.command_output_start = None;
+                self.screen.prompt_regions.clear();
+                self.screen.prompt_start = None;
+                self.screen.links.clear();
+                self.screen.link_start = None;
+                self.screen.underline_spans.clear();
+            }
+            ClearMode::Scroll => {
+                if !self.screen.grid.is_empty() {
+                    self.screen.grid.scroll_off_screen(self.screen_rows);
+                }
+            }
+        }
+    }
+
+    fn erase_in_line(&mut self, params: &str) {
+        let (before, after) = match params {
+            "" | "0" => (false, true),
+            "1" => (true, false),
+            "2" => (true, true),
+            _ => return,
+        };
+        if self.screen.pending_newlines > 0 {
+            return;
+        }
+        let (row, column) = (self.screen.grid.row, self.screen.grid.column);
+        if after {
+            self.erase_cells(row, column..usize::MAX, false);
+        }
+        if before {
+            self.erase_cells(row, 0..column, true);
+        }
+    }
+
+    fn erase_in_display(&mut self, params: &str, config: &Config, colors: &Colors) {
+        self.flush_pending_newlines(config, colors);
+        let row = self.screen.grid.row;
+        if params == "1" {
+            for above in self.screen.grid.screen_top(self.screen_rows)..row {
+                self.erase_cells(above, 0..usize::MAX, true);
+            }
+        } else {
+            for below in row + 1..self.screen.grid.lines.len() {
+                self.erase_cells(below, 0..usize::MAX, false);
+            }
+        }
+        self.erase_in_line(params);
+    }
+
+    fn erase_cells(&mut self, row: usize, columns: Range<usize>, blank: bool) {
+        let (start, start_chars) = self.screen.grid.offset(row, columns.start);
+        let (end, end_chars) = self.screen.grid.offset(row, columns.end);
+        if start == end {
+            return;
+        }
+        let inserted = if blank { end_chars - start_chars } else { 0 };
+        if blank {
+            self.screen.grid.blank_cells(row, columns);
+        } else {
+            self.screen.grid.truncate_line(row, columns.start);
+        }
+        self.output_changed = true;
+        let range = start..end;
+        self.screen.links.retain_mut(|(link, _)| {
+            link.start = map_offset(link.start, &range, inserted);
+            link.end = map_offset(link.end, &range, inserted);
+            link.start < link.end
+        });
+        let char_range = start_chars..end_chars;
+        self.screen.underline_spans.retain_mut(|(span, _, _)| {
+            span.start = map_offset(span.start, &char_range, inserted);
+            span.end = map_offset(span.end, &char_range, inserted);
+            span.start < span.end
+        });
+        for regions in [
+            &mut self.screen.prompt_regions,
+            &mut self.screen.command_regions,
+        ] {
+            regions.retain_mut(|region| {
+                region.start = map_offset(region.start, &char_range, inserted);
+                region.end = map_offset(region.end, &char_range, inserted);
+                region.start < region.end
+            });
+        }
+        for start in [
+            &mut self.screen.prompt_start,
+            &mut self.screen.command_output_start,
+        ] {
+            *start = start.map(|start| map_offset(start, &char_range, inserted));
+        }
+    }
+
+    fn set_private_mode(&mut self, mode: u32, enabled: bool, config: &Config) {
+        match mode {
+            1 => self.modes.application_cursor = enabled,
+            7 => self.modes.autowrap = enabled,
+            25 => self.modes.cursor_visible = enabled,
+            47 | 1047 | 1049 => self.set_alt_screen(enabled),
+            1000 | 1002 | 1003 => self.modes.mouse_reporting = enabled,
+            1004 => self.modes.focus_reporting = enabled,
+            2004 => self.modes.bracketed_paste = enabled,
+            2026 if config.synchronized_output => {
+                self.synchronized_output = enabled.then(Instant::now);
+            }
+            _ => {}
+        }
+    }
+
+    fn set_alt_screen(&mut self, enabled: bool) {
+        if enabled == self.modes.alt_screen {
+            return;
+        }
+        self.modes.alt_screen = enabled;
+        let screen = match self.primary_screen.take() {
+            Some(primary) if !enabled => primary,
+            _ => Screen::default(),
+        };
+        let previous = mem::replace(&mut self.screen, screen);
+        if enabled {
+            self.primary_screen = Some(previous);
+        }
+        self.output_changed = true;
+    }
+
+    fn soft_reset(&mut self, config: &Config, colors: &Colors) {
+        self.reset_sgr(colors);
+        self.modes = TerminalModes {
+            alt_screen: self.modes.alt_screen,
+            ..TerminalModes::new(config.wrap_lines)
+        };
+        self.kitty_keyboard_stack.clear();
+    }
+
+    fn reset_sgr(&mut self, colors: &Colors) {
+        self.current_format.color = colors.white;
+        self.underline_style = UnderlineStyle::None;
+        self.current_format.italics = false;
+        self.current_format.background = Color32::TRANSPARENT;
+        self.bold = false;
+        self.faint = false;
+        self.strikethrough = false;
+    }
+
+    fn is_synchronized_output_active(&self) -> bool {
+        self.synchronized_output
+            .is_some_and(|since| since.elapsed() < SYNCHRONIZED_OUTPUT_TIMEOUT)
+    }
+
+    fn take_layout_update(&mut self) -> bool {
+        if self.is_synchronized_output_active() {
+            return false;
+        }
+        mem::take(&mut self.layout_dirty)
+    }
+
+    fn append_new_output(
+        &mut self,
+        new_output: &[u8],
+        config: &Config,
+        colors: &mut Colors,
+    ) -> bool {
+        let mut text_to_append = mem::take(&mut self.utf8_tail);
+        self.output_changed = false;
+
+        for &byte in new_output {
+            match byte {
+                _ if self.osc.is_some() => {
+                    let max_payload = config.max_osc_payload;
+                    let osc = self.osc.as_mut().unwrap();
+                    if osc.push(byte, max_payload) {
+                        let osc = self.osc.take().unwrap();
+                        if osc.overflowed {
+                            eprintln!("Discarding OSC sequence larger than {max_payload} bytes");
+                        } else {
+                            self.handle_osc(&String::from_utf8_lossy(&osc.payload), config, colors);
+                        }
+                    }
+                }
+                _ if self.dcs.is_some() => {
+                    let max_payload = config.max_osc_payload;
+                    let dcs = self.dcs.as_mut().unwrap();
+                    if dcs.push(byte, max_payload) {
+                        let dcs = self.dcs.take().unwrap();
+                        if !dcs.overflowed {
+                            self.handle_dcs(&String::from_utf8_lossy(&dcs.payload), config, colors);
+                        }
+                    }
+                }
+                b']' if self.partial_char_buffer == [b'\x1b'] => {
+                    self.partial_char_buffer.clear();
+                    self.osc = Some(OscParser::default());
+                }
+                b'P' if self.partial_char_buffer == [b'\x1b'] => {
+                    self.partial_char_buffer.clear();
+                    self.dcs = Some(OscParser::default());
+                }
+                b'\x1b' => {
+                    if !text_to_append.is_empty() {
+                        self.append_text(&text_to_append, config, colors);
+                        text_to_append.clear();
+                    }
+                    self.partial_char_buffer.clear();
+                    self.partial_char_buffer.push(byte);
+                }
+                b'[' if self.partial_char_buffer == [b'\x1b'] => {
+                    self.partial_char_buffer.push(byte);
+                }
+                0x05 => {
+                    self.replies.extend_from_slice(config.answerback.as_bytes());
+                }
+                b'\r' => {
+                    if !text_to_append.is_empty() {
+                        self.append_text(&text_to_append, config, colors);
+                        text_to_append.clear();
+                    }
+                    if self.screen.pending_newlines == 0 {
+                        self.screen.grid.carriage_return();
+                    }
+                }
+                0x08 => {
+                    if !text_to_append.is_empty() {
+                        self.append_text(&text_to_append, config, colors);
+                        text_to_append.clear();
+                    }
+                    if self.screen.pending_newlines == 0 {
+                        self.screen.grid.backspace();
+                    }
+                }
+                0x7f => {}
+                0x07 => {
+                    if config.bell == BellStyle::Visual {
+                        self.bell = true;
+                    }
+                }
+                b'\n' if config.smart_newline => {
+                    self.screen.pending_newlines += 1;
+                }
+                b'\n' => {
+                    if !text_to_append.is_empty() {
+                        self.append_text(&text_to_append, config, colors);
+                        text_to_append.clear();
+                    }
+                    self.append_text(&[byte], config, colors);
+                }
+                _ if self.partial_char_buffer.starts_with(b"\x1b[") => match byte {
+                    0x40..=0x7e => {
+                        self.partial_char_buffer.push(byte);
+                        let sequence = mem::take(&mut self.partial_char_buffer);
+                        self.handle_csi(&String::from_utf8_lossy(&sequence[2..]), config, colors);
+                    }
+                    0x20..=0x3f => self.partial_char_buffer.push(byte),
+                    _ => self.partial_char_buffer.clear(),
+                },
+                // Escape intermediates such as the `(` in `ESC ( B` wait for their final byte.
+                0x20..=0x2f if !self.partial_char_buffer.is_empty() => {
+                    self.partial_char_buffer.push(byte);
+                }
+                _ if !self.partial_char_buffer.is_empty() => {
+                    if self.partial_char_buffer == [b'\x1b'] {
+                        match byte {
+                            b'=' => self.modes.application_keypad = true,
+                            b'>' => self.modes.application_keypad = false,
+                            _ => {}
+                        }
+                    }
+                    self.partial_char_buffer.clear();
+                }
+                _ => {
+                    if self.screen.pending_newlines > 0 {
+                        text_to_append
+                            .extend(std::iter::repeat_n(b'\n', self.screen.pending_newlines));
+                        self.screen.pending_newlines = 0;
+                    }
+                    text_to_append.push(byte);
+                }
+            }
+        }
+        let complete = text_to_append.len() - incomplete_utf8_len(&text_to_append);
+        self.utf8_tail = text_to_append.split_off(complete);
+        if !text_to_append.is_empty() {
+            self.append_text(&text_to_append, config, colors);
+        }
+        self.output_changed
+    }
+}
+
+struct TerminalApp {
+    config_path: PathBuf,
+    config_modified: Option<SystemTime>,
+    config_polled_at: Instant,
+    state_path: PathBuf,
+    stdin_buffer: Option<Arc<Mutex<Vec<u8>>>>,
+    stdin_partial: Vec<u8>,
+    stdin_text: String,
+    passthrough: Vec<String>,
+    repaint_ctx: Arc<OnceLock<egui::Context>>,
+    shared_reader: Option<SharedReader>,
+    sessions: Vec<Session>,
+    active_session: usize,
+    font_size: f32,
+    history: Vec<String>,
+    history_index: Option<usize>,
+    history_draft: String,
+    input_mode: InputMode,
+    pending_focus: Option<FocusTarget>,
+    active_profile: String,
+    focused: bool,
+    input_rect: Option<egui::Rect>,
+    input_scrollable: bool,
+    viewport_width: f32,
+    viewport_height: f32,
+    palette_dirty_since: Option<Instant>,
+    show_hex_dump: bool,
+    show_mirror: bool,
+    window_geometry: Option<WindowGeometry>,
+    window_position_checked: bool,
+    process_name_polled_at: Instant,
+    notification: Option<(Instant, String)>,
+    notification_pending: bool,
+    search_case_sensitive: bool,
+    window_title: String,
+    background_image: Option<egui::TextureHandle>,
+    colors: Colors,
+    config: Config,
+}
+
+impl TerminalApp {
+    fn base_background(&self) -> Color32 {
+        let background = if self.config.transparent_background {
+            self.colors.background
+        } else {
+            composite_over(self.colors.background, Color32::BLACK)
+        };
+        match self.session().bell_flash {
+            Some(started_at) => {
+                let progress =
+                    started_at.elapsed().as_secs_f32() / BELL_FLASH_DURATION.as_secs_f32();
+                background.lerp_to_gamma(Color32::WHITE, 0.5 * (1.0 - progress.min(1.0)))
+            }
+            None => background,
+        }
+    }
+
+    fn session(&self) -> &Session {
+        &self.sessions[self.active_session]
+    }
+
+    fn session_mut(&mut self) -> &mut Session {
+        &mut self.sessions[self.active_session]
+    }
+
+    fn set_font_size(&mut self, size: f32) {
+        let size = effective_font_size(size, self.config.min_font_size)
+            .clamp(MIN_ZOOM_FONT_SIZE, MAX_ZOOM_FONT_SIZE);
+        if size == self.font_size {
+            return;
+        }
+        self.font_size = size;
+        for session in &mut self.sessions {
+            session.current_format.font_id.size = size;
+            session.layout_dirty = true;
+        }
+    }
+
+    fn write_to_pty(&mut self, bytes: &[u8]) {
+        self.session_mut().write_to_pty(bytes);
+    }
+
+    fn title(&self) -> String {
+        self.session().title()
+    }
+
+    fn poll_session(&mut self, index: usize, ctx: &egui::Context) {
+        let session = &mut self.sessions[index];
+        let Some(shell) = &session.shell else {
+            return;
+        };
+        let (new_output, output_remaining) = {
+            let mut output_buffer = shell.output_buffer.lock().unwrap();
+            let new_output = take_budget(&mut output_buffer, self.config.max_bytes_per_frame);
+            (new_output, !output_buffer.is_empty())
+        };
+        let stderr_output = mem::take(&mut *shell.stderr_buffer.lock().unwrap());
+        if output_remaining {
+            ctx.request_repaint();
+        }
+        session.record_raw_output(&new_output, self.config.hex_dump_bytes);
+        if let Some(burst_lines) = self.config.pager_burst_lines
+            && !session.pager
+            && session.stick_to_bottom
+            && new_output.iter().filter(|&&byte| byte == b'\n').count()
+                > burst_lines.max(session.screen_rows)
+        {
+            session.pager = true;
+            session.stick_to_bottom = false;
+            session.pager_target = Some(session.screen.grid.len());
+        }
+        if !new_output.is_empty()
+            && session.append_new_output(&new_output, &self.config, &mut self.colors)
+        {
+            session.layout_dirty = true;
+        }
+        if !stderr_output.is_empty() {
+            session.append_stderr(&stderr_output, &self.config, &self.colors);
+            session.layout_dirty = true;
+        }
+        if !session.replies.is_empty() {
+            let replies = mem::take(&mut session.replies);
+            session.write_to_pty(&replies);
+        }
+        if mem::take(&mut session.bell) {
+            session.bell_flash = Some(Instant::now());
+            session.unseen_bell = index != self.active_session;
+        }
+        if let Some(line) = session.notification.take() {
+            self.notification = Some((Instant::now(), line));
+            self.notification_pending = true;
+        }
+        if mem::take(&mut session.palette_changed) {
+            self.mark_palette_dirty();
+        }
+        self.poll_shell_exit(index, ctx);
+    }
+
+    fn poll_shell_exit(&mut self, index: usize, ctx: &egui::Context) {
+        let session = &mut self.sessions[index];
+        let Some(shell) = &mut session.shell else {
+            return;
+        };
+        if !shell.reader_finished.load(Ordering::Acquire)
+            || !shell.output_buffer.lock().unwrap().is_empty()
+        {
+            return;
+        }
+        let status = match shell.child.try_wait() {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                ctx.request_repaint_after(Duration::from_millis(50));
+                return;
+            }
+            Err(e) => {
+                eprintln!("Failed to query the shell's exit status: {e}");
+                portable_pty::ExitStatus::with_exit_code(1)
+            }
+        };
+        session.shell = None;
+        match self.config.on_shell_exit {
+            ShellExitAction::Close if self.sessions.len() > 1 => self.close_session(index, ctx),
+            ShellExitAction::Close => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+            ShellExitAction::Notice | ShellExitAction::Respawn => {
+                let notice = exit_notice(
+                    &status,
+                    self.config.on_shell_exit == ShellExitAction::Respawn,
+                );
+                self.sessions[index].show_exit_notice(&notice, &self.config, &self.colors);
+            }
+        }
+    }
+
+    fn respawn_shell(&mut self) {
+        let pty_size = self.session().pty_size;
+        let shell = match spawn_shell(
+            &self.config,
+            &self.passthrough,
+            pty_size,
+            self.repaint_ctx.clone(),
+            self.shared_reader.as_ref(),
+        ) {
+            Ok(shell) => shell,
+            Err(e) => {
+                eprintln!("Failed to restart the shell: {e:#}");
+                return;
+            }
+        };
+        let session = &mut self.sessions[self.active_session];
+        session.shell = Some(shell);
+        session.modes = TerminalModes::new(self.config.wrap_lines);
+        session.kitty_keyboard_stack.clear();
+        session.screen.pending_newlines += 1;
+        session.stick_to_bottom = true;
+    }
+
+    fn mark_palette_dirty(&mut self) {
+        self.config.profiles.insert(
+            self.active_profile.clone(),
+            ColorPalette::from(&self.colors),
+        );
+        if self.config.autosave_palette {
+            self.palette_dirty_since = Some(Instant::now());
+        }
+    }
+
+    fn save_dirty_palette(&mut self) {
+        if !should_save_palette(
+            self.palette_dirty_since,
+            Instant::now(),
+            PALETTE_SAVE_DEBOUNCE,
+        ) {
+            return;
+        }
+        self.palette_dirty_since = None;
+        let profile =
+            (self.active_profile != DEFAULT_PROFILE).then_some(self.active_profile.as_str());
+        if let Err(e) = save_palette(
+            &self.config_path,
+            profile,
+            &ColorPalette::from(&self.colors),
+        ) {
+            eprintln!("Failed to save colors: {e}");
+        }
+    }
+
+    fn show_mirror_viewport(&mut self, ctx: &egui::Context) {
+        let Some(galley) = self.session().galley.clone() else {
+            return;
+        };
+        let background = self.base_background();
+        ctx.show_viewport_immediate(
+            egui::ViewportId::from_hash_of("mirror"),
+            egui::ViewportBuilder::default()
+                .with_title(format!("{} (mirror)", self.title()))
+                .with_inner_size([800.0, 500.0]),
+            |ctx, _| {
+                let frame = egui::Frame::central_panel(&ctx.style()).fill(background);
+                egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
+                    egui::ScrollArea::both()
+                        .stick_to_bottom(true)
+                        .show(ui, |ui| {
+                            ui.add(egui::Label::new(galley.clone()).selectable(false));
+                        });
+                });
+                if ctx.input(|i| i.viewport().close_requested()) {
+                    self.show_mirror = false;
+                }
+            },
+        );
+    }
+
+    fn reload_changed_palette(&mut self) {
+        let modified = modified_time(&self.config_path);
+        if modified == self.config_modified {
+            return;
+        }
+        self.config_modified = modified;
+        let palette = match load_colors(&self.config_path) {
+            Ok(palette) => palette,
+            Err(e) => {
+                eprintln!("{e:#}. Keeping the current palette.");
+                return;
+            }
+        };
+        self.config
+            .profiles
+            .insert(DEFAULT_PROFILE.to_string(), palette);
+        if let Ok(config) = load_config(&self.config_path) {
+            self.config.tab_width = config.tab_width;
+            self.config.profiles.extend(config.profiles);
+        }
+        if let Some(palette) = self.config.profiles.get(&self.active_profile) {
+            let previous_white = self.colors.white;
+            self.colors = Colors::from(palette.clone());
+            for session in &mut self.sessions {
+                if session.current_format.color == previous_white {
+                    session.current_format.color = self.colors.white;
+                }
+            }
+        }
+    }
+
+    fn track_window_geometry(&mut self, ctx: &egui::Context) {
+        let (inner_rect, outer_rect, monitor_size, minimized) = ctx.input(|i| {
+            let viewport = i.viewport();
+            (
+                viewport.inner_rect,
+                viewport.outer_rect,
+                viewport.monitor_size,
+                viewport.minimized.unwrap_or(false),
+            )
+        });
+        if minimized {
+            return;
+        }
+        if !self.window_position_checked
+            && let (Some(outer_rect), Some(monitor_size)) = (outer_rect, monitor_size)
+        {
+            self.window_position_checked = true;
+            let position = visible_position(outer_rect.min, outer_rect.size(), monitor_size);
+            if position != outer_rect.min {
+                ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(position));
+            }
+        }
+        if let Some(inner_rect) = inner_rect {
+            self.window_geometry = Some(WindowGeometry {
+                width: inner_rect.width(),
+                height: inner_rect.height(),
+                x: outer_rect.map(|rect| rect.min.x),
+                y: outer_rect.map(|rect| rect.min.y),
+            });
+        }
+    }
+
+    fn export_scrollback(&self) -> Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let (extension, contents) = if self.config.export_sgr {
+            ("ansi", self.session().scrollback_to_sgr())
+        } else {
+            ("txt", self.session().scrollback_to_plain())
+        };
+        let path = self
+            .config_path
+            .with_file_name(format!("scrollback-{timestamp}.{extension}"));
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    fn cycle_profile(&mut self) {
+        let next_profile = self
+            .config
+            .profiles
+            .range::<String, _>((Bound::Excluded(&self.active_profile), Bound::Unbounded))
+            .next()
+            .or_else(|| self.config.profiles.iter().next())
+            .map(|(name, palette)| (name.clone(), palette.clone()));
+        let Some((name, palette)) = next_profile else {
+            return;
+        };
+
+        self.colors = Colors::from(palette);
+        for session in &mut self.sessions {
+            session.current_format.color = self.colors.white;
+        }
+        self.active_profile = name;
+
+        if self.config.remember_profile {
+            let state = State {
+                active_profile: Some(self.active_profile.clone()),
+                ..load_state(&self.state_path).unwrap_or_default()
+            };
+            if let Err(e) = save_state(&self.state_path, &state) {
+                eprintln!("Failed to save state: {e}");
+            }
+        }
+    }
+
+    fn switch_session(&mut self, index: usize) {
+        if index == self.active_session || index >= self.sessions.len() {
+            return;
+        }
+        self.report_focus(self.active_session, false);
+        self.active_session = index;
+        self.sessions[index].unseen_bell = false;
+        self.report_focus(index, true);
+    }
+
+    fn report_focus(&mut self, index: usize, focused: bool) {
+        let session = &mut self.sessions[index];
+        if self.focused && session.modes.focus_reporting {
+            session.write_to_pty(focus_event_bytes(focused));
+        }
+    }
+
+    fn open_session(&mut self) {
+        let pty_size = self.session().pty_size;
+        let shell = match spawn_shell(
+            &self.config,
+            &self.passthrough,
+            pty_size,
+            self.repaint_ctx.clone(),
+            self.shared_reader.as_ref(),
+        ) {
+            Ok(shell) => shell,
+            Err(e) => {
+                eprintln!("Failed to open a new tab: {e:#}");
+                return;
+            }
+        };
+        let mut session = Session::new(&self.config, &self.colors, self.font_size, pty_size);
+        session.scrollback_lines = tab_scrollback_lines(&self.config, self.sessions.len());
+        session.shell = Some(shell);
+        self.sessions.push(session);
+        self.switch_session(self.sessions.len() - 1);
+    }
+
+    fn close_session(&mut self, index: usize, ctx: &egui::Context) {
+        if let Some(shell) = &mut self.sessions[index].shell.take() {
+            let _ = shell.child.kill();
+            let _ = shell.child.wait();
+        }
+        if self.sessions.len() == 1 {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            return;
+        }
+        self.sessions.remove(index);
+        let was_active = index == self.active_session;
+        if self.active_session > index || self.active_session == self.sessions.len() {
+            self.active_session -= 1;
+        }
+        if was_active {
+            self.sessions[self.active_session].unseen_bell = false;
+            self.report_focus(self.active_session, true);
+        }
+    }
+
+    fn handle_tab_keys(&mut self, ctx: &egui::Context) {
+        // Consumed here, before show_terminal encodes the remaining keys for the shell.
+        let (open_tab, close_tab, cycle_tabs) = ctx.input_mut(|i| {
+            let cycle = if i.consume_key(
+                egui::Modifiers::CTRL | egui::Modifiers::SHIFT,
+                egui::Key::Tab,
+            ) {
+                -1
+            } else if i.consume_key(egui::Modifiers::CTRL, egui::Key::Tab) {
+                1
+            } else {
+                0
+            };
+            (
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::T),
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::W),
+                cycle,
+            )
+        });
+        if open_tab {
+            self.open_session();
+        }
+        if close_tab {
+            self.close_session(self.active_session, ctx);
+        }
+        if cycle_tabs != 0 {
+            let count = self.sessions.len() as isize;
+            let next = (self.active_session as isize + cycle_tabs).rem_euclid(count);
+            self.switch_session(next as usize);
+        }
+    }
+
+    fn show_terminal(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let (scroll_area, scrolled_up) = self.terminal_scroll_area(ctx);
+        let scroll_area_response = scroll_area
+            .stick_to_bottom(self.session().stick_to_bottom)
+            .show(ui, |ui| self.show_output(ctx, ui));
+        self.update_scroll_state(ctx, ui, &scroll_area_response, scrolled_up);
+        self.paint_overlays(ui, scroll_area_response.inner_rect);
+        self.handle_view_keys(ctx);
+        self.show_input(ctx, ui);
+
+        if self.config.show_status_line {
+            let session = self.session();
+            ui.label(
+                egui::RichText::new(format!(
+                    "{} | fg: {}",
+                    mode_status(session.modes),
+                    palette_color_name(&self.colors, session.current_format.color)
+                        .unwrap_or("custom")
+                ))
+                .monospace()
+                .color(self.colors.grey),
+            );
+        }
+    }
+
+    fn terminal_scroll_area(&mut self, ctx: &egui::Context) -> (egui::ScrollArea, bool) {
+        let session = &mut self.sessions[self.active_session];
+        if session.take_layout_update() {
+            session.galley = None;
+            session.search_dirty = true;
+        }
+        let mut scroll_area = egui::ScrollArea::new([!session.modes.autowrap, true]);
+        if !session.modes.autowrap {
+            let delta_x = ctx.input_mut(|i| mem::take(&mut i.smooth_scroll_delta.x));
+            session.horizontal_offset = clamp_horizontal_offset(
+                session.horizontal_offset - delta_x,
+                session.content_width,
+                self.viewport_width,
+            );
+            scroll_area = scroll_area.horizontal_scroll_offset(session.horizontal_offset);
+        }
+        let pages = mem::take(&mut session.scroll_pages);
+        if pages != 0 {
+            scroll_area = scroll_area.vertical_scroll_offset(pager_scroll_offset(
+                session.scroll_offset_y,
+                pages,
+                self.viewport_height,
+            ));
+        }
+        let scroll_to_top = mem::take(&mut session.scroll_to_top);
+        if scroll_to_top {
+            scroll_area = scroll_area.vertical_scroll_offset(0.0);
+        }
+        let mut scrolled_up = pages < 0 || scroll_to_top;
+        let pointer_over_input = ctx
+            .input(|i| i.pointer.hover_pos())
+            .zip(self.input_rect)
+            .is_some_and(|(pointer, rect)| rect.contains(pointer));
+        let wheel_to_terminal = !pointer_over_input
+            || wheel_target(pointer_over_input, self.input_scrollable) == WheelTarget::Terminal;
+        if wheel_to_terminal && (pointer_over_input || self.config.kinetic_scrolling) {
+            let mut delta_y = ctx.input_mut(|i| mem::take(&mut i.smooth_scroll_delta.y));
+            if self.config.kinetic_scrolling {
+                (delta_y, session.scroll_velocity) =
+                    kinetic_step(session.scroll_velocity, delta_y, self.config.kinetic_decay);
+                if session.scroll_velocity != 0.0 {
+                    ctx.request_repaint();
+                }
+            }
+            if delta_y != 0.0 {
+                if delta_y > 0.0 {
+                    session.stick_to_bottom = false;
+                    scrolled_up = true;
+                }
+                scroll_area = scroll_area.vertical_scroll_offset(session.scroll_offset_y - delta_y);
+            }
+        }
+        (scroll_area, scrolled_up)
+    }
+
+    fn show_output(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let row_width = ui.available_width();
+        let (galley, scroll_anchor) = self.output_galley(ctx, ui);
+        let highlight_index = ui.painter().add(egui::Shape::Noop);
+        let prompt_background_index = ui.painter().add(egui::Shape::Noop);
+        let prompt_flash_index = ui.painter().add(egui::Shape::Noop);
+        let search_highlight_index = ui.painter().add(egui::Shape::Noop);
+        let label_response = ui.add(egui::Label::new(galley.clone()).selectable(true));
+        if let Some(bold_galley) = &self.session().bold_galley {
+            ui.painter().galley(
+                label_response.rect.min + egui::vec2(FAUX_BOLD_OFFSET, 0.0),
+                bold_galley.clone(),
+                Color32::TRANSPARENT,
+            );
+        }
+        if self.pending_focus == Some(FocusTarget::Terminal) {
+            label_response.request_focus();
+            self.pending_focus = None;
+        }
+        let rect = label_response.rect;
+        self.paint_search_matches(ui, &galley, rect, search_highlight_index);
+        self.scroll_to_targets(ui, &galley, rect, scroll_anchor);
+        self.handle_output_pointer(ctx, ui, &galley, &label_response);
+        self.paint_prompt_flash(ui, &galley, rect, row_width, prompt_flash_index);
+        self.paint_regions(ui, &galley, rect, row_width, prompt_background_index);
+        self.paint_cursor(ui, &galley, rect, row_width, highlight_index);
+    }
+
+    fn output_galley(&mut self, ctx: &egui::Context, ui: &egui::Ui) -> (Arc<Galley>, Option<f32>) {
+        let session = &mut self.sessions[self.active_session];
+        let wrap_width = if session.modes.autowrap {
+            ui.available_width()
+        } else {
+            f32::INFINITY
+        };
+        if let Some(galley) = &session.galley
+            && galley.job.wrap.max_width == wrap_width
+            && galley.pixels_per_point == ctx.pixels_per_point()
+        {
+            return (galley.clone(), None);
+        }
+        let font_id = &session.current_format.font_id;
+        let (mut layout_job, bold_job) = match &session.galley {
+            Some(galley) => (
+                (*galley.job).clone(),
+                session.bold_galley.as_ref().map(|bold| (*bold.job).clone()),
+            ),
+            None => (
+                session.screen.grid.layout_job(font_id),
+                session.screen.grid.bold_layout_job(font_id),
+            ),
+        };
+        layout_job.wrap.max_width = wrap_width;
+        session.bold_galley = bold_job.map(|mut bold_job| {
+            bold_job.wrap.max_width = wrap_width;
+            ui.fonts(|fonts| fonts.layout_job(bold_job))
+        });
+        let galley = ui.fonts(|fonts| fonts.layout_job(layout_job));
+        let mut scroll_anchor = None;
+        if self.config.preserve_scroll_on_resize
+            && !session.stick_to_bottom
+            && let Some(previous) = &session.galley
+            && previous.job.wrap.max_width != wrap_width
+        {
+            scroll_anchor = Some(anchored_scroll_offset(
+                previous,
+                &galley,
+                session.scroll_offset_y,
+            ));
+        }
+        session.galley = Some(galley.clone());
+        (galley, scroll_anchor)
+    }
+
+    fn paint_search_matches(
+        &self,
+        ui: &egui::Ui,
+        galley: &Galley,
+        rect: egui::Rect,
+        shape_index: egui::layers::ShapeIdx,
+    ) {
+        let session = self.session();
+        if session.search_query.is_none() || session.search_matches.is_empty() {
+            return;
+        }
+        let origin = rect.min.to_vec2();
+        let clip = ui.clip_rect();
+        let visible = galley.cursor_from_pos(clip.left_top() - rect.min).index
+            ..=galley.cursor_from_pos(clip.right_bottom() - rect.min).index;
+        let text = &galley.job.text;
+        let mut shapes = Vec::new();
+        let (mut byte_index, mut char_index) = (0, 0);
+        for (index, range) in session.search_matches.iter().enumerate() {
+            let (Some(before), Some(found)) =
+                (text.get(byte_index..range.start), text.get(range.clone()))
+            else {
+                break;
+            };
+            let start = char_index + before.chars().count();
+            char_index = start + found.chars().count();
+            byte_index = range.end;
+            if char_index < *visible.start() {
+                continue;
+            }
+            if start > *visible.end() {
+                break;
+            }
+            let start_rect = galley
+                .pos_from_cursor(egui::text::CCursor::new(start))
+                .translate(origin);
+            let end_rect = galley
+                .pos_from_cursor(egui::text::CCursor::new(char_index))
+                .translate(origin);
+            let right = if end_rect.top() == start_rect.top() {
+                end_rect.left()
+            } else {
+                rect.right()
+            };
+            let color = if session.search_index == Some(index) {
+                self.colors.bright_yellow.gamma_multiply(0.6)
+            } else {
+                self.colors.yellow.gamma_multiply(0.3)
+            };
+            shapes.push(egui::Shape::rect_filled(
+                egui::Rect::from_min_max(
+                    start_rect.left_top(),
+                    egui::pos2(right, start_rect.bottom()),
+                ),
+                0.0,
+                color,
+            ));
+        }
+        ui.painter().set(shape_index, egui::Shape::Vec(shapes));
+    }
+
+    fn scroll_to_targets(
+        &mut self,
+        ui: &egui::Ui,
+        galley: &Galley,
+        rect: egui::Rect,
+        scroll_anchor: Option<f32>,
+    ) {
+        let session = self.session_mut();
+        if let Some(offset) = scroll_anchor {
+            let top = rect.top() + offset;
+            let anchor_rect = egui::Rect::from_x_y_ranges(rect.x_range(), top..=top);
+            ui.scroll_to_rect(anchor_rect, Some(egui::Align::Min));
+        }
+        let targets = [
+            (session.pager_target.take(), egui::Align::Min),
+            (session.search_target.take(), egui::Align::Center),
+        ];
+        for (byte_index, align) in targets {
+            if let Some(byte_index) = byte_index
+                && let Some(before) = galley.job.text.get(..byte_index)
+            {
+                let cursor = egui::text::CCursor::new(before.chars().count());
+                let target_rect = galley.pos_from_cursor(cursor).translate(rect.min.to_vec2());
+                ui.scroll_to_rect(target_rect, Some(align));
+            }
+        }
+    }
+
+    fn handle_output_pointer(
+        &self,
+        ctx: &egui::Context,
+        ui: &egui::Ui,
+        galley: &Galley,
+        label_response: &egui::Response,
+    ) {
+        let session = self.session();
+        let text = &galley.job.text;
+        let hovered_char = label_response.hover_pos().map(|pointer| {
+            galley
+                .cursor_from_pos(pointer - label_response.rect.min)
+                .index
+        });
+        if let Some(key) = egui::Key::from_name(&self.config.copy_command_output_key)
+            && ctx.input(|i| i.key_pressed(key))
+            && let Some(range) = command_region_at(
+                &session.screen.command_regions,
+                hovered_char.unwrap_or(usize::MAX),
+            )
+            && let Some(output) =
+                text.get(char_to_byte_index(text, range.start)..char_to_byte_index(text, range.end))
+        {
+            let output = output.trim_end_matches(['\r', '\n']);
+            ctx.copy_text(if self.config.collapse_progress {
+                collapse_rewrites(output)
+            } else {
+                output.to_string()
+            });
+        }
+        let hovered_link = hovered_char.and_then(|char_index| {
+            link_target(
+                &session.screen.links,
+                text,
+                char_to_byte_index(text, char_index),
+                self.config.issue_url_template.as_deref(),
+                self.config.commit_url_template.as_deref(),
+            )
+        });
+        if let Some((range, url)) = hovered_link {
+            ctx.set_cursor_icon(egui::CursorIcon::PointingHand);
+            let origin = label_response.rect.min.to_vec2();
+            let start = galley
+                .pos_from_cursor(egui::text::CCursor::new(
+                    text[..range.start].chars().count(),
+                ))
+                .translate(origin);
+            let end = galley
+                .pos_from_cursor(egui::text::CCursor::new(text[..range.end].chars().count()))
+                .translate(origin);
+            let color = galley
+                .job
+                .sections
+                .iter()
+                .find(|section| section.byte_range.contains(&range.start))
+                .map_or(self.colors.white, |section| section.format.color);
+            let left = label_response.rect.min.x;
+            for rect in region_rects(start, end, left, left + galley.rect.width()) {
+                ui.painter()
+                    .extend(underline_shapes(rect, UnderlineStyle::Straight, color));
+            }
+            if label_response.clicked() {
+                ctx.open_url(egui::OpenUrl::new_tab(url));
+            }
+        }
+    }
+
+    fn paint_prompt_flash(
+        &mut self,
+        ui: &egui::Ui,
+        galley: &Galley,
+        rect: egui::Rect,
+        row_width: f32,
+        shape_index: egui::layers::ShapeIdx,
+    ) {
+        let session = &mut self.sessions[self.active_session];
+        let Some((started_at, char_index)) = session.prompt_flash else {
+            return;
+        };
+        let progress = started_at.elapsed().as_secs_f32() / PROMPT_FLASH_DURATION.as_secs_f32();
+        if progress >= 1.0 {
+            session.prompt_flash = None;
+            return;
+        }
+        let cursor_rect = galley.pos_from_cursor(egui::text::CCursor::new(char_index));
+        let row_rect = egui::Rect::from_min_size(
+            egui::pos2(rect.min.x, rect.min.y + cursor_rect.min.y),
+            egui::vec2(row_width.max(galley.rect.width()), cursor_rect.height()),
+        );
+        ui.painter().set(
+            shape_index,
+            egui::Shape::rect_filled(
+                row_rect,
+                0.0,
+                self.colors
+                    .bright_blue
+                    .gamma_multiply(0.4 * (1.0 - progress)),
+            ),
+        );
+    }
+
+    fn paint_regions(
+        &self,
+        ui: &egui::Ui,
+        galley: &Galley,
+        rect: egui::Rect,
+        row_width: f32,
+        prompt_background_index: egui::layers::ShapeIdx,
+    ) {
+        let session = self.session();
+        let origin = rect.min.to_vec2();
+        let left = rect.min.x;
+        let clip_rect = ui.clip_rect();
+        let region_ends = |range: &Range<usize>| {
+            let start = galley
+                .pos_from_cursor(egui::text::CCursor::new(range.start))
+                .translate(origin);
+            let end = galley
+                .pos_from_cursor(egui::text::CCursor::new(range.end))
+                .translate(origin);
+            (end.max.y >= clip_rect.min.y).then_some((start, end))
+        };
+        if self.config.highlight_prompt {
+            let right = left + row_width.max(galley.rect.width());
+            let mut shapes = Vec::new();
+            for region in session.screen.prompt_regions.iter().rev() {
+                let Some((start, end)) = region_ends(region) else {
+                    break;
+                };
+                shapes.extend(
+                    region_rects(start, end, left, right)
+                        .into_iter()
+                        .map(|rect| {
+                            egui::Shape::rect_filled(
+                                rect,
+                                0.0,
+                                self.colors.grey.gamma_multiply(0.2),
+                            )
+                        }),
+                );
+            }
+            ui.painter()
+                .set(prompt_background_index, egui::Shape::Vec(shapes));
+        }
+        if self.config.underline_styles {
+            let right = left + galley.rect.width();
+            for (range, style, color) in session.screen.underline_spans.iter().rev() {
+                let Some((start, end)) = region_ends(range) else {
+                    break;
+                };
+                for rect in region_rects(start, end, left, right) {
+                    ui.painter().extend(underline_shapes(rect, *style, *color));
+                }
+            }
+        }
+    }
+
+    fn paint_cursor(
+        &self,
+        ui: &egui::Ui,
+        galley: &Galley,
+        rect: egui::Rect,
+        row_width: f32,
+        highlight_index: egui::layers::ShapeIdx,
+    ) {
+        let session = self.session();
+        let (cursor, cursor_row, cursor_x) = cursor_position(
+            galley,
+            session.screen.grid.cursor_offset().1,
+            session.screen.pending_newlines,
+        );
+        if self.config.highlight_cursor_line
+            && session.modes.cursor_visible
+            && let Some(last_row) = galley.rows.last()
+        {
+            let row_rect = cursor_row_rect(
+                rect.min,
+                row_width.max(galley.rect.width()),
+                cursor_row,
+                last_row.rect().height(),
+            );
+            ui.painter().set(
+                highlight_index,
+                egui::Shape::rect_filled(row_rect, 0.0, self.colors.grey.gamma_multiply(0.3)),
+            );
+        }
+        if !self.config.draw_cursor || !session.modes.cursor_visible {
+            return;
+        }
+        let font_id = &session.current_format.font_id;
+        let (glyph_width, row_height) =
+            ui.fonts(|fonts| (fonts.glyph_width(font_id, 'M'), fonts.row_height(font_id)));
+        let overhang = if session.screen.pending_newlines == 0 {
+            session.screen.grid.cursor_overhang()
+        } else {
+            0
+        };
+        let glyph_under_cursor = (session.screen.pending_newlines == 0 && overhang == 0)
+            .then(|| {
+                let layout = galley.layout_from_cursor(cursor);
+                let row = galley.rows.get(layout.row)?;
+                row.glyphs.get(layout.column).map(|glyph| glyph.chr)
+            })
+            .flatten()
+            .filter(|c| !c.is_whitespace());
+        let clip_rect = ui.clip_rect();
+        let first_visible_row = ((clip_rect.top() - rect.top()) / row_height)
+            .max(0.0)
+            .floor() as usize;
+        let visible_rows = (clip_rect.height() / row_height).ceil() as usize;
+        if !cursor_in_viewport(cursor_row, first_visible_row, visible_rows) {
+            return;
+        }
+        let cursor_rect = egui::Rect::from_min_size(
+            rect.min
+                + egui::vec2(
+                    cursor_x + overhang as f32 * glyph_width,
+                    cursor_row as f32 * row_height,
+                ),
+            egui::vec2(glyph_width, row_height),
+        );
+        match glyph_under_cursor {
+            Some(glyph) if self.config.invert_cursor_glyph => {
+                ui.painter()
+                    .rect_filled(cursor_rect, 0.0, self.colors.white);
+                ui.painter().text(
+                    cursor_rect.left_top(),
+                    egui::Align2::LEFT_TOP,
+                    glyph,
+                    font_id.clone(),
+                    cursor_glyph_color(self.config.cursor_text_color, self.colors.background),
+                );
+            }
+            _ => {
+                ui.painter()
+                    .rect_filled(cursor_rect, 0.0, self.colors.white.gamma_multiply(0.6));
+            }
+        }
+    }
+
+    fn update_scroll_state(
+        &mut self,
+        ctx: &egui::Context,
+        ui: &egui::Ui,
+        scroll_area_response: &egui::scroll_area::ScrollAreaOutput<()>,
+        scrolled_up: bool,
+    ) {
+        let session = &mut self.sessions[self.active_session];
+        let (glyph_width, row_height) = ui.fonts(|fonts| {
+            (
+                fonts.glyph_width(&session.current_format.font_id, 'M'),
+                fonts.row_height(&session.current_format.font_id),
+            )
+        });
+        (session.screen_rows, session.screen_cols) = grid_dimensions(
+            scroll_area_response.inner_rect.size(),
+            glyph_width,
+            row_height,
+        );
+        let pixels_per_point = ctx.pixels_per_point();
+        session.resize_pty(PtySize {
+            rows: session.screen_rows.min(u16::MAX as usize) as u16,
+            cols: session.screen_cols.min(u16::MAX as usize) as u16,
+            pixel_width: (scroll_area_response.inner_rect.width() * pixels_per_point) as u16,
+            pixel_height: (scroll_area_response.inner_rect.height() * pixels_per_point) as u16,
+        });
+        session.horizontal_offset = scroll_area_response.state.offset.x;
+        session.scroll_offset_y = scroll_area_response.state.offset.y;
+        session.content_width = scroll_area_response.content_size.x;
+        self.viewport_width = scroll_area_response.inner_rect.width();
+        self.viewport_height = scroll_area_response.inner_rect.height();
+
+        let max_offset_y =
+            scroll_area_response.content_size.y - scroll_area_response.inner_rect.height();
+        if !session.pager && !scrolled_up {
+            session.stick_to_bottom = stick_to_bottom_after_scroll(
+                session.stick_to_bottom,
+                scroll_area_response.state.offset.y,
+                max_offset_y,
+                scroll_area_response.content_size.y != session.content_height,
+            );
+        }
+        session.content_height = scroll_area_response.content_size.y;
+    }
+
+    fn paint_overlays(&self, ui: &egui::Ui, rect: egui::Rect) {
+        let session = self.session();
+        if self.config.show_scroll_percentage
+            && let Some(percentage) = scroll_percentage(
+                session.scroll_offset_y,
+                session.content_height,
+                self.viewport_height,
+            )
+        {
+            ui.painter().text(
+                rect.right_top() + egui::vec2(-8.0, 8.0),
+                egui::Align2::RIGHT_TOP,
+                format!("{percentage}%"),
+                FontId::new(12.0, FontFamily::Monospace),
+                self.colors.grey,
+            );
+        }
+
+        if self.config.show_command_duration
+            && let Some(result) = &session.last_command
+            && result.finished_at.elapsed() < COMMAND_RESULT_DISPLAY_DURATION
+        {
+            let color = match result.exit_code {
+                Some(0) => self.colors.green,
+                Some(_) => self.colors.red,
+                None => self.colors.grey,
+            };
+            ui.painter().text(
+                rect.right_bottom() + egui::vec2(-8.0, -8.0),
+                egui::Align2::RIGHT_BOTTOM,
+                format_command_result(result.duration, result.exit_code),
+                FontId::new(12.0, FontFamily::Monospace),
+                color,
+            );
+        }
+
+        if let Some((shown_at, line)) = &self.notification
+            && shown_at.elapsed() < COMMAND_RESULT_DISPLAY_DURATION
+        {
+            ui.painter().text(
+                rect.right_top() + egui::vec2(-8.0, 8.0),
+                egui::Align2::RIGHT_TOP,
+                line,
+                FontId::new(12.0, FontFamily::Monospace),
+                self.colors.bright_yellow,
+            );
+        }
+
+        if session.pager {
+            ui.painter().text(
+                rect.left_bottom() + egui::vec2(8.0, -8.0),
+                egui::Align2::LEFT_BOTTOM,
+                "-- PAGER (Space/b to page, q to quit) --",
+                FontId::new(12.0, FontFamily::Monospace),
+                self.colors.bright_yellow,
+            );
+        }
+
+        if let Some((shown_at, chord)) = &session.control_chord
+            && shown_at.elapsed() < CONTROL_CHORD_DISPLAY_DURATION
+        {
+            ui.painter().text(
+                rect.center_bottom() + egui::vec2(0.0, -8.0),
+                egui::Align2::CENTER_BOTTOM,
+                chord,
+                FontId::new(24.0, FontFamily::Monospace),
+                self.colors.bright_yellow,
+            );
+        }
+    }
+
+    fn handle_view_keys(&mut self, ctx: &egui::Context) {
+        let zoom_steps = ctx.input_mut(|i| {
+            let mut steps = 0.0;
+            for key in [egui::Key::Equals, egui::Key::Plus] {
+                while i.consume_key(egui::Modifiers::COMMAND, key) {
+                    steps += 1.0;
+                }
+            }
+            while i.consume_key(egui::Modifiers::COMMAND, egui::Key::Minus) {
+                steps -= 1.0;
+            }
+            steps
+        });
+        let zoom_delta = ctx.input(|i| i.zoom_delta());
+        if zoom_steps != 0.0 || zoom_delta != 1.0 {
+            self.set_font_size(self.font_size * zoom_delta + zoom_steps * FONT_SIZE_STEP);
+        }
+        let session = &mut self.sessions[self.active_session];
+
+        if let Some(key) = egui::Key::from_name(&self.config.pager_key)
+            && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, key))
+        {
+            session.pager = !session.pager;
+            session.stick_to_bottom = !session.pager;
+        }
+        if session.pager {
+            let command = ctx.input_mut(|i| {
+                let command = [
+                    egui::Key::Space,
+                    egui::Key::PageDown,
+                    egui::Key::B,
+                    egui::Key::PageUp,
+                    egui::Key::Q,
+                    egui::Key::Escape,
+                ]
+                .into_iter()
+                .find(|key| i.consume_key(egui::Modifiers::NONE, *key))
+                .and_then(pager_command);
+                i.events
+                    .retain(|event| !matches!(event, egui::Event::Text(_)));
+                command
+            });
+            match command {
+                Some(PagerCommand::PageDown) => session.scroll_pages += 1,
+                Some(PagerCommand::PageUp) => session.scroll_pages -= 1,
+                Some(PagerCommand::Quit) => {
+                    session.pager = false;
+                    session.stick_to_bottom = true;
+                }
+                None => {}
+            }
+        }
+
+        if !session.pager {
+            let line_mode = self.input_mode == InputMode::Line;
+            let input_empty = session.input_text.is_empty();
+            let command = ctx.input_mut(|i| {
+                [
+                    egui::Key::PageUp,
+                    egui::Key::PageDown,
+                    egui::Key::Home,
+                    egui::Key::End,
+                ]
+                .into_iter()
+                .flat_map(|key| [(key, true), (key, false)])
+                .find_map(|(key, shift)| {
+                    let command = scroll_command(key, shift, line_mode, input_empty)?;
+                    let modifiers = if shift {
+                        egui::Modifiers::SHIFT
+                    } else {
+                        egui::Modifiers::NONE
+                    };
+                    i.consume_key(modifiers, key).then_some(command)
+                })
+            });
+            match command {
+                Some(ScrollCommand::PageUp) => {
+                    session.scroll_pages -= 1;
+                    session.stick_to_bottom = false;
+                }
+                Some(ScrollCommand::PageDown) => session.scroll_pages += 1,
+                Some(ScrollCommand::Top) => {
+                    session.scroll_to_top = true;
+                    session.stick_to_bottom = false;
+                }
+                Some(ScrollCommand::Bottom) => session.stick_to_bottom = true,
+                None => {}
+            }
+        }
+
+        if let Some(key) = egui::Key::from_name(&self.config.mirror_key)
+            && ctx.input(|i| i.key_pressed(key))
+        {
+            self.show_mirror = !self.show_mirror;
+        }
+
+        if let Some(key) = egui::Key::from_name(&self.config.input_mode_toggle_key)
+            && ctx.input(|i| i.key_pressed(key))
+        {
+            self.input_mode = self.input_mode.toggled();
+        }
+
+        if let Some(key) = egui::Key::from_name(&self.config.profile_cycle_key)
+            && ctx.input(|i| i.key_pressed(key))
+        {
+            self.cycle_profile();
+        }
+    }
+
+    fn show_input(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let has_output_selection =
+            egui::text_selection::LabelSelectionState::load(ctx).has_selection();
+        match self.input_mode {
+            InputMode::Line => self.show_line_input(ctx, ui, has_output_selection),
+            InputMode::Raw => self.send_raw_input(ctx, ui, has_output_selection),
+        }
+    }
+
+    fn show_line_input(
+        &mut self,
+        ctx: &egui::Context,
+        ui: &mut egui::Ui,
+        has_output_selection: bool,
+    ) {
+        let session = &mut self.sessions[self.active_session];
+        let text_edit_output = egui::TextEdit::singleline(&mut session.input_text)
+            .return_key(None)
+            .desired_width(ui.available_width())
+            .hint_text("Type commands here...")
+            .font(FontId::new(self.font_size, FontFamily::Monospace))
+            .margin(egui::Margin::symmetric(5, 5))
+            .show(ui);
+        let text_edit_response = text_edit_output.response;
+        if enforce_input_limit(&mut session.input_text, self.config.max_input_length) {
+            ui.painter().rect_stroke(
+                text_edit_response.rect,
+                0.0,
+                egui::Stroke::new(1.0, self.colors.red),
+                egui::StrokeKind::Inside,
+            );
+        }
+        self.input_rect = Some(text_edit_response.rect);
+        self.input_scrollable =
+            text_edit_output.galley.size().y > text_edit_output.text_clip_rect.height() + 0.5;
+
+        if self.pending_focus == Some(FocusTarget::Input) {
+            text_edit_response.request_focus();
+            self.pending_focus = None;
+        }
+
+        let control_bytes: Vec<u8> = if session.search_query.is_some() {
+            Vec::new()
+        } else {
+            ctx.input(|i| {
+                i.events
+                    .iter()
+                    .filter_map(|event| match classify_event(event) {
+                        Some(InputEvent::Key(key, modifiers))
+                            if modifiers.ctrl && !modifiers.alt =>
+                        {
+                            control_byte(key)
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            })
+        };
+        if !control_bytes.is_empty() {
+            session.send_input(&control_bytes, &self.config);
+            session.stick_to_bottom = true;
+        }
+
+        let input_has_selection = text_edit_output
+            .cursor_range
+            .is_some_and(|range| !range.is_empty());
+        if text_edit_response.has_focus()
+            && ctx.input(|i| {
+                i.events.contains(&egui::Event::Copy)
+                    && copy_sends_interrupt(
+                        i.modifiers.shift,
+                        has_output_selection || input_has_selection,
+                    )
+            })
+        {
+            session.send_input(b"\x03", &self.config);
+        }
+
+        let history_key = ctx.input(|i| {
+            if i.key_pressed(egui::Key::ArrowUp) {
+                Some(true)
+            } else if i.key_pressed(egui::Key::ArrowDown) {
+                Some(false)
+            } else {
+                None
+            }
+        });
+        let history_index =
+            history_key.map(|older| history_step(self.history.len(), self.history_index, older));
+        if text_edit_response.has_focus()
+            && let Some(index) = history_index
+            && index != self.history_index
+        {
+            if self.history_index.is_none() && index.is_some() {
+                self.history_draft = mem::take(&mut session.input_text);
+            }
+            session.input_text = match index {
+                Some(index) => self.history[index].clone(),
+                None => mem::take(&mut self.history_draft),
+            };
+            self.history_index = index;
+            let mut state = text_edit_output.state;
+            let end = egui::text::CCursor::new(session.input_text.chars().count());
+            state
+                .cursor
+                .set_char_range(Some(egui::text::CCursorRange::one(end)));
+            state.store(ctx, text_edit_response.id);
+        }
+
+        let enters = if text_edit_response.has_focus() {
+            ctx.input(|i| {
+                i.events
+                    .iter()
+                    .filter(|event| {
+                        matches!(event, egui::Event::Key {
+                            key: egui::Key::Enter,
+                            pressed: true,
+                            modifiers,
+                            ..
+                        } if modifiers.matches_logically(egui::Modifiers::NONE))
+                    })
+                    .count()
+            })
+        } else {
+            0
+        };
+        let enters = if self.config.submit_every_enter {
+            enters
+        } else {
+            enters.min(1)
+        };
+        if enters > 0 {
+            let mut line = mem::take(&mut session.input_text);
+            if self.config.strip_ansi_input {
+                line = strip_ansi(&line);
+            }
+            if self.config.title_from_command {
+                session.command_title = title_from_command_line(&line);
+            }
+            self.history_index = None;
+            self.history_draft.clear();
+            session.write_to_pty(&submission_bytes(&line, enters));
+            if !line.is_empty() && self.history.last() != Some(&line) {
+                self.history.push(line);
+            }
+            session.stick_to_bottom = true;
+        }
+    }
+
+    fn send_raw_input(
+        &mut self,
+        ctx: &egui::Context,
+        ui: &mut egui::Ui,
+        has_output_selection: bool,
+    ) {
+        self.input_rect = None;
+        ui.label(
+            egui::RichText::new(format!(
+                "-- RAW INPUT ({} to toggle) --",
+                self.config.input_mode_toggle_key
+            ))
+            .monospace()
+            .color(self.colors.grey),
+        );
+
+        let session = &mut self.sessions[self.active_session];
+        let bytes = if session.search_query.is_some() {
+            Vec::new()
+        } else {
+            ctx.input(|i| {
+                raw_input_bytes(
+                    &i.events,
+                    egui::Key::from_name(&self.config.input_mode_toggle_key),
+                    session.modes,
+                    self.config.strip_ansi_input,
+                    copy_sends_interrupt(i.modifiers.shift, has_output_selection),
+                )
+            })
+        };
+        if !bytes.is_empty() {
+            if self.config.title_from_command && bytes.contains(&b'\r') {
+                let echoed_line = session.screen.grid.lines[session.screen.grid.row].text();
+                session.command_title = title_from_command_line(&echoed_line);
+            }
+            session.send_input(&bytes, &self.config);
+            session.stick_to_bottom = true;
+        }
+    }
+}
+
+fn control_byte(key: egui::Key) -> Option<u8> {
+    let name = key.name();
+    (name.len() == 1 && name.as_bytes()[0].is_ascii_alphabetic()).then(|| name.as_bytes()[0] & 0x1f)
+}
+
+fn encode_key(key: egui::Key, modifiers: egui::Modifiers, modes: TerminalModes) -> Option<Vec<u8>> {
+    // Ctrl+Alt is AltGr on many layouts; its composed character arrives as text.
+    if modifiers.ctrl
+        && !modifiers.alt
+        && let Some(byte) = control_byte(key)
+    {
+        return Some(vec![byte]);
+    }
+    let sequence: &[u8] = match key {
+        // egui-winit maps KeyCode::NumpadEnter to Key::Enter for both the logical and the
+        // physical key, and eframe exposes no raw winit events, so numpad Enter cannot send
+        // ESC O M in keypad mode and both Enters send CR.
+        egui::Key::Enter => b"\r",
+        egui::Key::Backspace => b"\x7f",
+        egui::Key::Tab => b"\t",
+        egui::Key::Escape => b"\x1b",
+        egui::Key::ArrowUp if modes.application_cursor => b"\x1bOA",
+        egui::Key::ArrowDown if modes.application_cursor => b"\x1bOB",
+        egui::Key::ArrowRight if modes.application_cursor => b"\x1bOC",
+        egui::Key::ArrowLeft if modes.application_cursor => b"\x1bOD",
+        egui::Key::Home if modes.application_cursor => b"\x1bOH",
+        egui::Key::End if modes.application_cursor => b"\x1bOF",
+        egui::Key::ArrowUp => b"\x1b[A",
+        egui::Key::ArrowDown => b"\x1b[B",
+        egui::Key::ArrowRight => b"\x1b[C",
+        egui::Key::ArrowLeft => b"\x1b[D",
+        egui::Key::Home => b"\x1b[H",
+        egui::Key::End => b"\x1b[F",
+        egui::Key::Insert => b"\x1b[2~",
+        egui::Key::Delete => b"\x1b[3~",
+        egui::Key::PageUp => b"\x1b[5~",
+        egui::Key::PageDown => b"\x1b[6~",
+        _ => return None,
+    };
+    Some(sequence.to_vec())
+}
+
+fn kitty_modifiers(modifiers: egui::Modifiers) -> u8 {
+    1 + u8::from(modifiers.shift) + 2 * u8::from(modifiers.alt) + 4 * u8::from(modifiers.ctrl)
+}
+
+fn encode_kitty_key(key: egui::Key, modifiers: egui::Modifiers) -> Option<Vec<u8>> {
+    let modifier = kitty_modifiers(modifiers);
+    let name = key.name();
+    let code = match key {
+        egui::Key::Escape => 27,
+        egui::Key::Enter | egui::Key::Tab | egui::Key::Backspace if modifier == 1 => return None,
+        egui::Key::Enter => 13,
+        egui::Key::Tab => 9,
+        egui::Key::Backspace => 127,
+        _ if modifiers.ctrl
+            && !modifiers.alt
+            && name.len() == 1
+            && name.as_bytes()[0].is_ascii_alphanumeric() =>
+        {
+            name.as_bytes()[0].to_ascii_lowercase()
+        }
+        _ => {
+            if modifier == 1 {
+                return None;
+            }
+            let sequence = match key {
+                egui::Key::ArrowUp => format!("\x1b[1;{modifier}A"),
+                egui::Key::ArrowDown => format!("\x1b[1;{modifier}B"),
+                egui::Key::ArrowRight => format!("\x1b[1;{modifier}C"),
+                egui::Key::ArrowLeft => format!("\x1b[1;{modifier}D"),
+                egui::Key::Home => format!("\x1b[1;{modifier}H"),
+                egui::Key::End => format!("\x1b[1;{modifier}F"),
+                egui::Key::Insert => format!("\x1b[2;{modifier}~"),
+                egui::Key::Delete => format!("\x1b[3;{modifier}~"),
+                egui::Key::PageUp => format!("\x1b[5;{modifier}~"),
+                egui::Key::PageDown => format!("\x1b[6;{modifier}~"),
+                _ => return None,
+            };
+            return Some(sequence.into_bytes());
+        }
+    };
+    let sequence = if modifier == 1 {
+        format!("\x1b[{code}u")
+    } else {
+        format!("\x1b[{code};{modifier}u")
+    };
+    Some(sequence.into_bytes())
+}
+
+fn caret_notation(byte: u8) -> Option<String> {
+    match byte {
+        0x00..=0x1f => Some(format!("^{}", (byte + 0x40) as char)),
+        0x7f => Some("^?".to_string()),
+        _ => None,
+    }
+}
+
+fn hex_dump(bytes: &[u8], start_offset: usize) -> String {
+    let mut dump = String::new();
+    for (index, chunk) in bytes.chunks(16).enumerate() {
+        dump.push_str(&format!("{:08x} ", start_offset + index * 16));
+        for column in 0..16 {
+            if column == 8 {
+                dump.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => dump.push_str(&format!(" {byte:02x}")),
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  |");
+        dump.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push_str("|\n");
+    }
+    dump
+}
+
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    stripped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputEvent<'a> {
+    Text(&'a str),
+    Paste(&'a str),
+    Copy,
+    Cut,
+    Key(egui::Key, egui::Modifiers),
+}
+
+fn classify_event(event: &egui::Event) -> Option<InputEvent<'_>> {
+    match event {
+        egui::Event::Text(text) | egui::Event::Ime(egui::ImeEvent::Commit(text)) => {
+            Some(InputEvent::Text(text))
+        }
+        egui::Event::Paste(text) => Some(InputEvent::Paste(text)),
+        egui::Event::Copy => Some(InputEvent::Copy),
+        egui::Event::Cut => Some(InputEvent::Cut),
+        egui::Event::Key {
+            key,
+            pressed: true,
+            modifiers,
+            ..
+        } => Some(InputEvent::Key(*key, *modifiers)),
+        _ => None,
+    }
+}
+
+fn raw_input_bytes(
+    events: &[egui::Event],
+    toggle_key: Option<egui::Key>,
+    modes: TerminalModes,
+    strip_ansi_paste: bool,
+    copy_interrupts: bool,
+) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for event in events.iter().filter_map(classify_event) {
+        match event {
+            InputEvent::Text(text) => bytes.extend_from_slice(text.as_bytes()),
+            InputEvent::Paste(text) => {
+                let text = if strip_ansi_paste {
+                    strip_ansi(text)
+                } else {
+                    text.to_string()
+                };
+                if modes.bracketed_paste {
+                    bytes.extend_from_slice(b"\x1b[200~");
+                    bytes.extend_from_slice(text.as_bytes());
+                    bytes.extend_from_slice(b"\x1b[201~");
+                } else {
+                    bytes.extend_from_slice(text.as_bytes());
+                }
+            }
+            InputEvent::Key(key, modifiers) if Some(key) != toggle_key => {
+                let kitty_sequence = (modes.kitty_keyboard_flags & 1 != 0)
+                    .then(|| encode_kitty_key(key, modifiers))
+                    .flatten();
+                if let Some(sequence) = kitty_sequence.or_else(|| encode_key(key, modifiers, modes))
+                {
+                    bytes.extend_from_slice(&sequence);
+                }
+            }
+            // The windowing layer turns Ctrl+C and Ctrl+X into clipboard events.
+            InputEvent::Copy if copy_interrupts => bytes.push(0x03),
+            InputEvent::Cut => bytes.push(0x18),
+            InputEvent::Copy | InputEvent::Key(..) => {}
+        }
+    }
+    bytes
+}
+
+fn copy_sends_interrupt(shift: bool, has_selection: bool) -> bool {
+    !shift && !has_selection
+}
+
+fn focus_event_bytes(focused: bool) -> &'static [u8] {
+    if focused { b"\x1b[I" } else { b"\x1b[O" }
+}
+
+fn mode_status(modes: TerminalModes) -> String {
+    let flags = [
+        (modes.alt_screen, "alt-screen"),
+        (modes.application_cursor, "app-cursor"),
+        (modes.application_keypad, "app-keypad"),
+        (modes.mouse_reporting, "mouse"),
+        (modes.focus_reporting, "focus-events"),
+        (modes.bracketed_paste, "bracketed-paste"),
+        (!modes.autowrap, "no-wrap"),
+        (!modes.cursor_visible, "cursor-hidden"),
+        (modes.kitty_keyboard_flags != 0, "kitty-keys"),
+    ];
+    let active: Vec<&str> = flags
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| *name)
+        .collect();
+    if active.is_empty() {
+        "normal".to_string()
+    } else {
+        active.join(" | ")
+    }
+}
+
+fn find_urls(text: &str) -> Vec<Range<usize>> {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    let url = URL.get_or_init(|| Regex::new(r#"https?://[^\s<>"'`]+"#).expect("valid regex"));
+    url.find_iter(text)
+        .filter_map(|found| {
+            let host_start = found.start() + found.as_str().find("://")? + 3;
+            let mut end = found.end();
+            while let Some(last) = text[host_start..end].chars().last() {
+                let unbalanced_paren = last == ')' && !text[host_start..end].contains('(');
+                if !matches!(last, '.' | ',' | ';' | ':' | '!' | '?' | ']' | '}')
+                    && !unbalanced_paren
+                {
+                    break;
+                }
+                end -= last.len_utf8();
+            }
+            (end > host_start).then_some(found.start()..end)
+        })
+        .collect()
+}
+
+fn line_bounds(text: &str, byte_index: usize) -> Range<usize> {
+    let line_start = text[..byte_index].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = text[byte_index..]
+        .find('\n')
+        .map_or(text.len(), |offset| byte_index + offset);
+    line_start..line_end
+}
+
+fn url_at(text: &str, byte_index: usize) -> Option<Range<usize>> {
+    let line = line_bounds(text, byte_index);
+    find_urls(&text[line.clone()])
+        .into_iter()
+        .map(|range| line.start + range.start..line.start + range.end)
+        .find(|range| range.contains(&byte_index))
+}
+
+fn link_target(
+    links: &[(Range<usize>, String)],
+    text: &str,
+    byte_index: usize,
+    issue_url_template: Option<&str>,
+    commit_url_template: Option<&str>,
+) -> Option<(Range<usize>, String)> {
+    if let Some(link) = links.iter().find(|(range, _)| range.contains(&byte_index)) {
+        return Some(link.clone());
+    }
+    if let Some(range) = url_at(text, byte_index) {
+        return Some((range.clone(), text[range].to_string()));
+    }
+    let line = line_bounds(text, byte_index);
+    find_references(&text[line.clone()], issue_url_template, commit_url_template)
+        .into_iter()
+        .map(|(range, url)| (line.start + range.start..line.start + range.end, url))
+        .find(|(range, _)| range.contains(&byte_index))
+}
+
+fn find_references(
+    text: &str,
+    issue_url_template: Option<&str>,
+    commit_url_template: Option<&str>,
+) -> Vec<(Range<usize>, String)> {
+    let mut references = Vec::new();
+    let bytes = text.as_bytes();
+    let mut start = 0;
+    while start < bytes.len() {
+        let is_word_start = bytes[start].is_ascii_alphanumeric() || bytes[start] == b'#';
+        if !is_word_start || (start > 0 && bytes[start - 1].is_ascii_alphanumeric()) {
+            start += 1;
+            continue;
+        }
+        let end = start
+            + bytes[start..]
+                .iter()
+                .position(|byte| !byte.is_ascii_alphanumeric() && *byte != b'#')
+                .unwrap_or(bytes.len() - start);
+        let word = &text[start..end];
+        if let Some(template) = issue_url_template
+            && let Some(number) = word.strip_prefix('#')
+            && !number.is_empty()
+            && number.bytes().all(|byte| byte.is_ascii_digit())
+        {
+            references.push((start..end, template.replace("{}", number)));
+        } else if let Some(template) = commit_url_template
+            && (7..=40).contains(&word.len())
+            && word
+                .bytes()
+                .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+            && word.bytes().any(|byte| byte.is_ascii_digit())
+            && word.bytes().any(|byte| byte.is_ascii_alphabetic())
+        {
+            references.push((start..end, template.replace("{}", word)));
+        }
+        start = end;
+    }
+    references
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WheelTarget {
+    Terminal,
+    Input,
+}
+
+fn wheel_target(pointer_over_input: bool, input_scrollable: bool) -> WheelTarget {
+    if pointer_over_input && input_scrollable {
+        WheelTarget::Input
+    } else {
+        WheelTarget::Terminal
+    }
+}
+
+fn centered_grid_rect(available: egui::Rect, grid_size: egui::Vec2) -> egui::Rect {
+    let size = grid_size.min(available.size());
+    egui::Rect::from_center_size(available.center(), size)
+}
+
+fn pager_command(key: egui::Key) -> Option<PagerCommand> {
+    match key {
+        egui::Key::Space | egui::Key::PageDown => Some(PagerCommand::PageDown),
+        egui::Key::B | egui::Key::PageUp => Some(PagerCommand::PageUp),
+        egui::Key::Q | egui::Key::Escape => Some(PagerCommand::Quit),
+        _ => None,
+    }
+}
+
+fn scroll_command(
+    key: egui::Key,
+    shift: bool,
+    line_mode: bool,
+    input_empty: bool,
+) -> Option<ScrollCommand> {
+    match key {
+        egui::Key::PageUp if shift || line_mode => Some(ScrollCommand::PageUp),
+        egui::Key::PageDown if shift || line_mode => Some(ScrollCommand::PageDown),
+        egui::Key::Home if shift && (!line_mode || input_empty) => Some(ScrollCommand::Top),
+        egui::Key::End if shift && (!line_mode || input_empty) => Some(ScrollCommand::Bottom),
+        _ => None,
+    }
+}
+
+fn stick_to_bottom_after_scroll(
+    stuck: bool,
+    offset: f32,
+    max_offset: f32,
+    content_changed: bool,
+) -> bool {
+    offset >= max_offset - STICK_TO_BOTTOM_TOLERANCE || (stuck && content_changed)
+}
+
+fn pager_scroll_offset(offset: f32, pages: i32, page_height: f32) -> f32 {
+    (offset + pages as f32 * page_height).max(0.0)
+}
+
+fn clamp_horizontal_offset(offset: f32, content_width: f32, viewport_width: f32) -> f32 {
+    offset.clamp(0.0, (content_width - viewport_width).max(0.0))
+}
+
+fn cursor_in_viewport(cursor_row: usize, first_visible_row: usize, visible_rows: usize) -> bool {
+    (first_visible_row..first_visible_row + visible_rows).contains(&cursor_row)
+}
+
+fn cursor_glyph_color(configured: Option<[u8; 4]>, background: Color32) -> Color32 {
+    configured.map_or(background, |[r, g, b, a]| {
+        Color32::from_rgba_unmultiplied(r, g, b, a)
+    })
+}
+
+fn sgr_for_format(format: &TextFormat, bold: bool) -> String {
+    let [r, g, b, _] = format.color.to_array();
+    let mut sgr = format!("\x1b[0;38;2;{r};{g};{b}");
+    if bold {
+        sgr.push_str(";1");
+    }
+    if format.background != Color32::TRANSPARENT {
+        let [r, g, b, _] = format.background.to_array();
+        sgr.push_str(&format!(";48;2;{r};{g};{b}"));
+    }
+    if format.italics {
+        sgr.push_str(";3");
+    }
+    if format.underline != egui::Stroke::NONE {
+        sgr.push_str(";4");
+    }
+    if format.strikethrough != egui::Stroke::NONE {
+        sgr.push_str(";9");
+    }
+    sgr.push('m');
+    sgr
+}
+
+fn cursor_position(
+    galley: &Galley,
+    cursor: usize,
+    pending_newlines: usize,
+) -> (egui::text::CCursor, usize, f32) {
+    let cursor = egui::text::CCursor::new(cursor);
+    let row = galley.layout_from_cursor(cursor).row + pending_newlines;
+    let x = if pending_newlines > 0 {
+        0.0
+    } else {
+        galley.pos_from_cursor(cursor).min.x
+    };
+    (cursor, row, x)
+}
+
+fn cursor_row_rect(origin: egui::Pos2, width: f32, row: usize, row_height: f32) -> egui::Rect {
+    egui::Rect::from_min_size(
+        origin + egui::vec2(0.0, row as f32 * row_height),
+        egui::vec2(width, row_height),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnderlineStyle {
+    None,
+    Straight,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+fn parse_underline_style(sub_param: &str) -> UnderlineStyle {
+    match sub_param {
+        "0" => UnderlineStyle::None,
+        "2" => UnderlineStyle::Double,
+        "3" => UnderlineStyle::Curly,
+        "4" => UnderlineStyle::Dotted,
+        "5" => UnderlineStyle::Dashed,
+        _ => UnderlineStyle::Straight,
+    }
+}
+
+fn underline_shapes(rect: egui::Rect, style: UnderlineStyle, color: Color32) -> Vec<egui::Shape> {
+    let stroke = egui::Stroke::new(1.0, color);
+    let y = rect.max.y - 1.0;
+    let segments = |on: f32, period: f32| {
+        let mut shapes = Vec::new();
+        let mut x = rect.min.x;
+        while x < rect.max.x {
+            let end = (x + on).min(rect.max.x);
+            shapes.push(egui::Shape::line_segment(
+                [egui::pos2(x, y), egui::pos2(end, y)],
+                stroke,
+            ));
+            x += period;
+        }
+        shapes
+    };
+    match style {
+        UnderlineStyle::None => Vec::new(),
+        UnderlineStyle::Straight => segments(f32::INFINITY, f32::INFINITY),
+        UnderlineStyle::Double => vec![
+            egui::Shape::line_segment(
+                [
+                    egui::pos2(rect.min.x, y - 2.0),
+                    egui::pos2(rect.max.x, y - 2.0),
+                ],
+                stroke,
+            ),
+            egui::Shape::line_segment(
+                [egui::pos2(rect.min.x, y), egui::pos2(rect.max.x, y)],
+                stroke,
+            ),
+        ],
+        UnderlineStyle::Curly => {
+            let steps = ((rect.width() / 1.5).ceil() as usize).max(1);
+            let points = (0..=steps)
+                .map(|step| {
+                    let x = rect.min.x + rect.width() * step as f32 / steps as f32;
+                    egui::pos2(x, y - 1.5 + 1.5 * (x * std::f32::consts::PI / 3.0).sin())
+                })
+                .collect();
+            vec![egui::Shape::line(points, stroke)]
+        }
+        UnderlineStyle::Dotted => segments(1.0, 3.0),
+        UnderlineStyle::Dashed => segments(4.0, 7.0),
+    }
+}
+
+fn region_rects(start: egui::Rect, end: egui::Rect, left: f32, right: f32) -> Vec<egui::Rect> {
+    if (start.min.y - end.min.y).abs() < 0.5 {
+        return vec![egui::Rect::from_min_max(
+            start.min,
+            egui::pos2(end.min.x, end.max.y),
+        )];
+    }
+    let mut rects = vec![egui::Rect::from_min_max(
+        start.min,
+        egui::pos2(right, start.max.y),
+    )];
+    if end.min.y > start.max.y + 0.5 {
+        rects.push(egui::Rect::from_min_max(
+            egui::pos2(left, start.max.y),
+            egui::pos2(right, end.min.y),
+        ));
+    }
+    rects.push(egui::Rect::from_min_max(
+        egui::pos2(left, end.min.y),
+        egui::pos2(end.min.x, end.max.y),
+    ));
+    rects
+}
+
+fn kinetic_step(velocity: f32, delta: f32, decay: f32) -> (f32, f32) {
+    if delta != 0.0 {
+        return (delta, delta);
+    }
+    let next = velocity * decay.clamp(0.0, 0.99);
+    (velocity, if next.abs() < 0.5 { 0.0 } else { next })
+}
+
+fn effective_font_size(size: f32, min_size: f32) -> f32 {
+    let min_size = if min_size.is_finite() {
+        min_size.max(1.0)
+    } else {
+        1.0
+    };
+    if size.is_finite() {
+        size.max(min_size)
+    } else {
+        min_size
+    }
+}
+
+fn grid_dimensions(area: egui::Vec2, glyph_width: f32, row_height: f32) -> (usize, usize) {
+    let cells = |extent: f32, cell: f32| {
+        if cell > f32::EPSILON && extent.is_finite() {
+            ((extent / cell).floor() as usize).max(1)
+        } else {
+            1
+        }
+    };
+    (cells(area.y, row_height), cells(area.x, glyph_width))
+}
+
+fn repaint_interval(focused: bool, has_pending_output: bool, unfocused: Duration) -> Duration {
+    if focused || has_pending_output {
+        Duration::from_millis(1)
+    } else {
+        unfocused
+    }
+}
+
+fn scroll_percentage(offset: f32, content_height: f32, viewport_height: f32) -> Option<u32> {
+    let max_offset = content_height - viewport_height;
+    if max_offset <= 0.0 || offset >= max_offset - 1.0 {
+        return None;
+    }
+    Some((offset.max(0.0) / max_offset * 100.0).round() as u32)
+}
+
+impl eframe::App for TerminalApp {
+    fn clear_color(&self, _visuals: &egui::Visuals) -> [f32; 4] {
+        self.base_background().to_normalized_gamma_f32()
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        let Some(window) = self.window_geometry else {
+            return;
+        };
+        let state = State {
+            window: Some(window),
+            ..load_state(&self.state_path).unwrap_or_default()
+        };
+        if let Err(e) = save_state(&self.state_path, &state) {
+            eprintln!("Failed to save state: {e}");
+        }
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.track_window_geometry(ctx);
+        if self.config_polled_at.elapsed() >= CONFIG_POLL_INTERVAL {
+            self.reload_changed_palette();
+            self.config_polled_at = Instant::now();
+        }
+        if self.process_name_polled_at.elapsed() >= PROCESS_NAME_POLL_INTERVAL {
+            for session in &mut self.sessions {
+                session.process_name = session
+                    .shell
+                    .as_ref()
+                    .and_then(|shell| foreground_process_name(&*shell.master));
+            }
+            self.process_name_polled_at = Instant::now();
+        }
+
+        for index in (0..self.sessions.len()).rev() {
+            self.poll_session(index, ctx);
+        }
+        if self.session().shell.is_none()
+            && self.config.on_shell_exit == ShellExitAction::Respawn
+            && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Enter))
+        {
+            self.respawn_shell();
+        }
+
+        self.save_dirty_palette();
+        for session in &mut self.sessions {
+            if session.trim_scrollback(self.config.scrollback_trim_per_frame) {
+                ctx.request_repaint();
+            }
+        }
+
+        let focused = ctx.input(|i| i.focused);
+        if focused != self.focused {
+            if self.session().modes.focus_reporting {
+                self.write_to_pty(focus_event_bytes(focused));
+            }
+            self.focused = focused;
+        }
+
+        if mem::take(&mut self.notification_pending) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::RequestUserAttention(
+                egui::UserAttentionType::Informational,
+            ));
+        }
+
+        let title = self.title();
+        if title != self.window_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.window_title = title;
+        }
+
+        if let Some(key) = egui::Key::from_name(&self.config.hex_dump_toggle_key)
+            && ctx.input(|i| i.key_pressed(key))
+        {
+            self.show_hex_dump = !self.show_hex_dump;
+        }
+
+        if ctx.input_mut(|i| {
+            i.consume_key(
+                egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                egui::Key::S,
+            )
+        }) {
+            let message = match self.export_scrollback() {
+                Ok(path) => format!("Saved scrollback to {}", path.display()),
+                Err(e) => format!("{e:#}"),
+            };
+            self.notification = Some((Instant::now(), message));
+        }
+
+        self.handle_tab_keys(ctx);
+        if self.sessions.len() > 1 {
+            let mut selected = None;
+            let mut new_tab = false;
+            let tabs_frame = egui::Frame::side_top_panel(&ctx.style()).fill(self.base_background());
+            egui::TopBottomPanel::top("tabs")
+                .frame(tabs_frame)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        for index in 0..self.sessions.len() {
+                            let active = index == self.active_session;
+                            let label = egui::RichText::new(self.sessions[index].title())
+                                .monospace()
+                                .color(if active {
+                                    self.colors.white
+                                } else if self.sessions[index].unseen_bell {
+                                    self.colors.bright_yellow
+                                } else {
+                                    self.colors.grey
+                                });
+                            if ui.selectable_label(active, label).clicked() {
+                                selected = Some(index);
+                            }
+                        }
+                        new_tab = ui.button("+").clicked();
+                    });
+                });
+            if let Some(index) = selected {
+                self.switch_session(index);
+            }
+            if new_tab {
+                self.open_session();
+            }
+        }
+
+        let line_mode = self.input_mode == InputMode::Line;
+        let search_opened = ctx.input_mut(|i| {
+            i.consume_key(
+                egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                egui::Key::F,
+            ) || (line_mode && i.consume_key(egui::Modifiers::COMMAND, egui::Key::F))
+        });
+        let search_frame = egui::Frame::side_top_panel(&ctx.style()).fill(self.base_background());
+        let session = &mut self.sessions[self.active_session];
+        if search_opened {
+            session.search_query = match session.search_query.take() {
+                Some(_) => {
+                    self.pending_focus =
+                        Some(focus_target(self.config.initial_focus, self.input_mode));
+                    None
+                }
+                None => Some(String::new()),
+            };
+        }
+        if let Some(query) = &mut session.search_query {
+            let mut close_search = false;
+            let mut query_changed = false;
+            let mut step = None;
+            let match_count = session.search_matches.len();
+            let match_label = match session.search_index {
+                Some(index) => format!("{}/{match_count}", index + 1),
+                None => format!("{match_count} matches"),
+            };
+            egui::TopBottomPanel::bottom("search")
+                .frame(search_frame)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(
+                            egui::RichText::new("Search:")
+                                .monospace()
+                                .color(self.colors.grey),
+                        );
+                        let case_response = ui.toggle_value(&mut self.search_case_sensitive, "Aa");
+                        ui.label(
+                            egui::RichText::new(match_label)
+                                .monospace()
+                                .color(self.colors.grey),
+                        );
+                        let response = ui.add(
+                            egui::TextEdit::singleline(query)
+                                .return_key(None)
+                                .font(egui::TextStyle::Monospace)
+                                .desired_width(f32::INFINITY),
+                        );
+                        if search_opened {
+                            response.request_focus();
+                        }
+                        query_changed = response.changed() || case_response.changed();
+                        let focused = response.has_focus();
+                        step = ui.input_mut(|i| {
+                            if !focused {
+                                None
+                            } else if i.consume_key(egui::Modifiers::SHIFT, egui::Key::Enter)
+                                || i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp)
+                            {
+                                Some(false)
+                            } else if i.consume_key(egui::Modifiers::NONE, egui::Key::Enter)
+                                || i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown)
+                            {
+                                Some(true)
+                            } else {
+                                None
+                            }
+                        });
+                        close_search =
+                            response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Escape));
+                    });
+                });
+            if query_changed || mem::take(&mut session.search_dirty) {
+                session.search_matches = search(
+                    &session.screen.grid.text(),
+                    query,
+                    self.search_case_sensitive,
+                );
+                session.search_index = if query_changed {
+                    (self.config.search_as_you_type && !session.search_matches.is_empty())
+                        .then_some(0)
+                } else {
+                    session
+                        .search_index
+                        .filter(|&index| index < session.search_matches.len())
+                };
+                if query_changed && session.search_index.is_some() {
+                    session.search_target = Some(session.search_matches[0].start);
+                    session.stick_to_bottom = false;
+                }
+            }
+            if let Some(forward) = step {
+                session.search_index =
+                    step_match(session.search_index, session.search_matches.len(), forward);
+                if let Some(index) = session.search_index {
+                    session.search_target = Some(session.search_matches[index].start);
+                    session.stick_to_bottom = false;
+                }
+            }
+            if close_search {
+                session.search_query = None;
+                session.search_matches.clear();
+                session.search_index = None;
+                self.pending_focus = Some(focus_target(self.config.initial_focus, self.input_mode));
+            }
+        }
+
+        if let Some(stdin_buffer) = &self.stdin_buffer {
+            let input = mem::take(&mut *stdin_buffer.lock().unwrap());
+            append_piped_text(
+                &mut self.stdin_text,
+                &mut self.stdin_partial,
+                &input,
+                self.config.scrollback_lines,
+            );
+        }
+        if !self.stdin_text.is_empty() {
+            let stdin_frame =
+                egui::Frame::side_top_panel(&ctx.style()).fill(self.base_background());
+            egui::SidePanel::right("stdin")
+                .frame(stdin_frame)
+                .resizable(true)
+                .show(ctx, |ui| {
+                    egui::ScrollArea::vertical()
+                        .stick_to_bottom(true)
+                        .show(ui, |ui| {
+                            ui.label(
+                                egui::RichText::new(&self.stdin_text)
+                                    .monospace()
+                                    .color(self.colors.white),
+                            );
+                        });
+                });
+        }
+
+        if self.show_hex_dump {
+            let hex_dump_frame =
+                egui::Frame::side_top_panel(&ctx.style()).fill(self.base_background());
+            egui::SidePanel::right("hex_dump")
+                .frame(hex_dump_frame)
+                .resizable(true)
+                .show(ctx, |ui| {
+                    let session = &mut self.sessions[self.active_session];
+                    if session.hex_dump_text.is_none() {
+                        let (front, back) = session.raw_output.as_slices();
+                        let bytes = [front, back].concat();
+                        session.hex_dump_text = Some(hex_dump(&bytes, session.raw_output_offset));
+                    }
+                    egui::ScrollArea::vertical()
+                        .stick_to_bottom(true)
+                        .show(ui, |ui| {
+                            ui.label(
+                                egui::RichText::new(
+                                    session.hex_dump_text.as_deref().unwrap_or_default(),
+                                )
+                                .monospace()
+                                .color(self.colors.white),
+                            );
+                        });
+                });
+        }
+
+        let frame = egui::Frame::central_panel(&ctx.style()).fill(self.base_background());
+        egui::CentralPanel::default().frame(frame).show(ctx, |ui| {
+            if let Some(texture) = &self.background_image {
+                let opacity = clamp_opacity(self.config.background_image_opacity);
+                ui.painter().image(
+                    texture.id(),
+                    ui.max_rect(),
+                    egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                    Color32::WHITE.gamma_multiply(opacity),
+                );
+            }
+            match self.config.fixed_grid {
+                Some(grid) => {
+                    let font_id = self.session().current_format.font_id.clone();
+                    let (glyph_width, row_height) = ui.fonts(|fonts| {
+                        (fonts.glyph_width(&font_id, 'M'), fonts.row_height(&font_id))
+                    });
+                    let input_height = row_height + 10.0 + ui.spacing().item_spacing.y;
+                    let grid_size = egui::vec2(
+                        grid.cols as f32 * glyph_width,
+                        grid.rows as f32 * row_height + input_height,
+                    );
+                    let rect = centered_grid_rect(ui.max_rect(), grid_size);
+                    ui.scope_builder(egui::UiBuilder::new().max_rect(rect), |ui| {
+                        self.show_terminal(ctx, ui);
+                    });
+                }
+                None => self.show_terminal(ctx, ui),
+            }
+
+            let has_pending_output = self.sessions.iter().any(|session| {
+                session
+                    .shell
+                    .as_ref()
+                    .is_some_and(|shell| !shell.output_buffer.lock().unwrap().is_empty())
+            });
+            ctx.request_repaint_after(repaint_interval(
+                ctx.input(|i| i.focused),
+                has_pending_output,
+                Duration::from_millis(self.config.unfocused_repaint_ms),
+            ));
+        });
+
+        let session = self.session_mut();
+        if let Some(started_at) = session.bell_flash {
+            if started_at.elapsed() < BELL_FLASH_DURATION {
+                ctx.request_repaint_after(Duration::from_millis(16));
+            } else {
+                session.bell_flash = None;
+            }
+        }
+
+        if self.show_mirror {
+            self.show_mirror_viewport(ctx);
+        }
+    }
+}
+
+fn take_budget(buffer: &mut Vec<u8>, budget: Option<usize>) -> Vec<u8> {
+    match budget {
+        Some(budget) if buffer.len() > budget => buffer.drain(..budget.max(1)).collect(),
+        _ => mem::take(buffer),
+    }
+}
+
+fn incomplete_utf8_len(bytes: &[u8]) -> usize {
+    for (len, &byte) in bytes.iter().rev().take(3).enumerate() {
+        let len = len + 1;
+        let needed = match byte {
+            0x80..=0xbf => continue,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return 0,
+        };
+        return if len < needed { len } else { 0 };
+    }
+    0
+}
+
+fn append_piped_text(text: &mut String, partial: &mut Vec<u8>, input: &[u8], max_lines: usize) {
+    partial.extend_from_slice(input);
+    let valid = match std::str::from_utf8(partial) {
+        Ok(valid) => valid.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => partial.len(),
+    };
+    let complete: Vec<u8> = partial.drain(..valid).collect();
+    text.push_str(&strip_ansi(&String::from_utf8_lossy(&complete)));
+    let excess = text.matches('\n').count().saturating_sub(max_lines);
+    if excess > 0
+        && let Some((index, _)) = text.match_indices('\n').nth(excess - 1)
+    {
+        text.drain(..=index);
+    }
+}
+
+fn anchored_scroll_offset(previous: &Galley, galley: &Galley, offset: f32) -> f32 {
+    let anchor = previous.cursor_from_pos(egui::vec2(0.0, offset));
+    let within_row = offset - previous.pos_from_cursor(anchor).top();
+    galley.pos_from_cursor(anchor).top() + within_row
+}
+
+fn spawn_reader(
+    mut reader: Box<dyn Read + Send>,
+    output_buffer: Arc<Mutex<Vec<u8>>>,
+    repaint_ctx: Arc<OnceLock<egui::Context>>,
+    max_buffered: usize,
+) -> Arc<AtomicBool> {
+    let finished = Arc::new(AtomicBool::new(false));
+    let reader_finished = finished.clone();
+    thread::spawn(move || {
+        let mut buffer = [0; 1024];
+        loop {
+            while output_buffer.lock().unwrap().len() >= max_buffered {
+                if let Some(ctx) = repaint_ctx.get() {
+                    ctx.request_repaint();
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            match reader.read(&mut buffer) {
+                Ok(count) if count > 0 => {
+                    let mut output = output_buffer.lock().unwrap();
+                    output.extend_from_slice(&buffer[..count]);
+                    if let Some(ctx) = repaint_ctx.get() {
+                        ctx.request_repaint();
+                    }
+                }
+                Ok(_) => {
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Error reading from PTY: {e}");
+                    break;
+                }
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        reader_finished.store(true, Ordering::Release);
+        if let Some(ctx) = repaint_ctx.get() {
+            ctx.request_repaint();
+        }
+    });
+    finished
+}
+
+struct ReadSource {
+    file: fs::File,
+    output_buffer: Arc<Mutex<Vec<u8>>>,
+    finished: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Default)]
+struct SharedReader {
+    sources: Arc<Mutex<Vec<ReadSource>>>,
+}
+
+impl SharedReader {
+    fn spawn(repaint_ctx: Arc<OnceLock<egui::Context>>, max_buffered: usize) -> Self {
+        let reader = Self::default();
+        let shared = reader.clone();
+        thread::spawn(move || {
+            loop {
+                if shared.read_ready(max_buffered, SHARED_READER_POLL_INTERVAL)
+                    && let Some(ctx) = repaint_ctx.get()
+                {
+                    ctx.request_repaint();
+                }
+            }
+        });
+        reader
+    }
+
+    fn add(&self, file: fs::File, output_buffer: Arc<Mutex<Vec<u8>>>) -> Arc<AtomicBool> {
+        let finished = Arc::new(AtomicBool::new(false));
+        self.sources.lock().unwrap().push(ReadSource {
+            file,
+            output_buffer,
+            finished: finished.clone(),
+        });
+        finished
+    }
+
+    #[cfg(unix)]
+    fn read_ready(&self, max_buffered: usize, timeout: Duration) -> bool {
+        use std::os::fd::AsRawFd;
+        // Polled without the lock so add() never waits for the poll timeout. Only this thread
+        // removes sources, so the polled fds stay at the front of the list.
+        let mut fds: Vec<libc::pollfd> = self
+            .sources
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|source| libc::pollfd {
+                fd: source.file.as_raw_fd(),
+                events: if source.output_buffer.lock().unwrap().len() < max_buffered {
+                    libc::POLLIN
+                } else {
+                    0
+                },
+                revents: 0,
+            })
+            .collect();
+        let ready = unsafe {
+            libc::poll(
+                fds.as_mut_ptr(),
+                fds.len() as libc::nfds_t,
+                timeout.as_millis() as libc::c_int,
+            )
+        };
+        if ready <= 0 {
+            return false;
+        }
+        let mut buffer = [0; 4096];
+        let mut changed = false;
+        let mut fds = fds.iter();
+        self.sources.lock().unwrap().retain_mut(|source| {
+            if fds.next().is_none_or(|fd| fd.revents == 0) {
+                return true;
+            }
+            changed = true;
+            match source.file.read(&mut buffer) {
+                Ok(count) if count > 0 => {
+                    source
+                        .output_buffer
+                        .lock()
+                        .unwrap()
+                        .extend_from_slice(&buffer[..count]);
+                    true
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => true,
+                _ => {
+                    source.finished.store(true, Ordering::Release);
+                    false
+                }
+            }
+        });
+        changed
+    }
+
+    #[cfg(not(unix))]
+    fn read_ready(&self, _max_buffered: usize, timeout: Duration) -> bool {
+        thread::sleep(timeout);
+        false
+    }
+}
+
+#[cfg(unix)]
+fn pollable_reader(master: &dyn portable_pty::MasterPty) -> Option<fs::File> {
+    let fd = master.as_raw_fd()?;
+    let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) };
+    fd.try_clone_to_owned().ok().map(fs::File::from)
+}
+
+#[cfg(not(unix))]
+fn pollable_reader(_master: &dyn portable_pty::MasterPty) -> Option<fs::File> {
+    None
+}
+
+struct ShellProcess {
+    child: Box<dyn portable_pty::Child + Send + Sync>,
+    writer: Box<dyn Write + Send>,
+    master: Box<dyn portable_pty::MasterPty + Send>,
+    reader_finished: Arc<AtomicBool>,
+    output_buffer: Arc<Mutex<Vec<u8>>>,
+    stderr_buffer: Arc<Mutex<Vec<u8>>>,
+    _stderr_fifo: Option<StderrFifo>,
+}
+
+fn spawn_shell(
+    config: &Config,
+    passthrough: &[String],
+    size: PtySize,
+    repaint_ctx: Arc<OnceLock<egui::Context>>,
+    shared_reader: Option<&SharedReader>,
+) -> Result<ShellProcess> {
+    let pty_pair = native_pty_system().openpty(size)?;
+
+    let stderr_fifo = if !config.separate_stderr {
+        None
+    } else if passthrough.is_empty() {
+        eprintln!("separate_stderr only applies to commands given on the command line.");
+        None
+    } else {
+        create_stderr_fifo()
+            .inspect_err(|e| eprintln!("Failed to set up separate stderr: {e:#}."))
+            .ok()
+    };
+    let (program, args) = shell_invocation(&config.shell, passthrough);
+    let mut cmd = match &stderr_fifo {
+        Some((fifo, _)) => stderr_command(&program, &args, &fifo.path),
+        None => {
+            let mut cmd = CommandBuilder::new(&program);
+            cmd.args(&args);
+            cmd
+        }
+    };
+    if let Some(cwd) = &config.shell.cwd {
+        cmd.cwd(cwd);
+    }
+
+    let child = pty_pair.slave.spawn_command(cmd)?;
+
+    let master = pty_pair.master;
+    let writer = master.take_writer()?;
+    let max_buffered = config.max_buffered_output.max(MIN_BUFFERED_OUTPUT);
+    let output_buffer = Arc::new(Mutex::new(Vec::new()));
+    let stderr_buffer = Arc::new(Mutex::new(Vec::new()));
+    let reader_finished = match shared_reader.zip(pollable_reader(&*master)) {
+        Some((shared_reader, file)) => shared_reader.add(file, output_buffer.clone()),
+        None => spawn_reader(
+            master.try_clone_reader()?,
+            output_buffer.clone(),
+            repaint_ctx.clone(),
+            max_buffered,
+        ),
+    };
+
+    let stderr_fifo = stderr_fifo.map(|(fifo, file)| {
+        match shared_reader {
+            Some(shared_reader) => {
+                shared_reader.add(file, stderr_buffer.clone());
+            }
+            None => {
+                spawn_reader(
+                    Box::new(file),
+                    stderr_buffer.clone(),
+                    repaint_ctx,
+                    max_buffered,
+                );
+            }
+        }
+        fifo
+    });
+
+    Ok(ShellProcess {
+        child,
+        writer,
+        master,
+        reader_finished,
+        output_buffer,
+        stderr_buffer,
+        _stderr_fifo: stderr_fifo,
+    })
+}
+
+fn main() -> Result<()> {
+    let quiet = std::env::args()
+        .skip(1)
+        .take_while(|arg| arg != "--")
+        .any(|arg| arg == "--quiet");
+    let config_path = config_file_path("colors.toml", !quiet);
+    let state_path = config_file_path("state.toml", !quiet);
+    let report_load_errors = !quiet || config_path.exists();
+    let mut config = load_config(&config_path).unwrap_or_else(|e| {
+        if report_load_errors {
+            eprintln!("Failed to load config: {e}. Using default settings.");
+        }
+        Config::default()
+    });
+    let state = load_state(&state_path).unwrap_or_default();
+    let geometry = state.window.or(config.window).map(WindowGeometry::clamped);
+
+    let initial_size = geometry.map_or(egui::vec2(1920.0, 1080.0), |geometry| {
+        egui::vec2(geometry.width, geometry.height)
+    });
+    let initial_pty_size = estimated_pty_size(
+        initial_size,
+        effective_font_size(config.font_size, config.min_font_size),
+    );
+    let passthrough: Vec<String> = std::env::args()
+        .skip_while(|arg| arg != "--")
+        .skip(1)
+        .collect();
+    let repaint_ctx = Arc::new(OnceLock::<egui::Context>::new());
+    let max_buffered = config.max_buffered_output.max(MIN_BUFFERED_OUTPUT);
+    let shared_reader = config
+        .shared_reader
+        .then(|| SharedReader::spawn(repaint_ctx.clone(), max_buffered));
+    let shell = spawn_shell(
+        &config,
+        &passthrough,
+        initial_pty_size,
+        repaint_ctx.clone(),
+        shared_reader.as_ref(),
+    )?;
+
+    let stdin_buffer = (config.stdin_pane && !std::io::stdin().is_terminal()).then(|| {
+        let stdin_buffer = Arc::new(Mutex::new(Vec::new()));
+        spawn_reader(
+            Box::new(std::io::stdin()),
+            stdin_buffer.clone(),
+            repaint_ctx.clone(),
+            max_buffered,
+        );
+        stdin_buffer
+    });
+
+    let mut viewport = egui::ViewportBuilder::default()
+        .with_resizable(true)
+        .with_transparent(true)
+        .with_inner_size(initial_size);
+    if let Some(WindowGeometry {
+        x: Some(x),
+        y: Some(y),
+        ..
+    }) = geometry
+    {
+        viewport = viewport.with_position(egui::pos2(x, y));
+    }
+    let options = eframe::NativeOptions {
+        viewport,
+        ..Default::default()
+    };
+    eframe::run_native(
+        DEFAULT_TITLE,
+        options,
+        Box::new(|cc| {
+            let _ = repaint_ctx.set(cc.egui_ctx.clone());
+
+            let colors = load_colors_or_default(&config_path, !quiet, report_load_errors);
+
+            config
+                .profiles
+                .insert(DEFAULT_PROFILE.to_string(), ColorPalette::from(&colors));
+            for path in &config.import_palettes {
+                match import_palette(path) {
+                    Ok(palette) => {
+                        let name = path
+                            .file_stem()
+                            .map(|stem| stem.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| path.display().to_string());
+                        config.profiles.entry(name).or_insert(palette);
+                    }
+                    Err(e) => eprintln!("{e:#}"),
+                }
+            }
+            let saved_profile = if config.remember_profile {
+                state.active_profile
+            } else {
+                None
+            };
+            let active_profile = resolve_profile(saved_profile.as_deref(), &config.profiles);
+            let colors = Colors::from(config.profiles[&active_profile].clone());
+
+            cc.egui_ctx
+                .options_mut(|options| options.zoom_with_keyboard = false);
+            if let Some(path) = &config.font_path {
+                match load_font(path, config.font_fallback) {
+                    Ok(fonts) => cc.egui_ctx.set_fonts(fonts),
+                    Err(e) => eprintln!("{e:#}. Using the default monospace font."),
+                }
+            }
+
+            let background_image = config.background_image.as_deref().and_then(|path| {
+                match load_background_image(path) {
+                    Ok(image) => Some(cc.egui_ctx.load_texture(
+                        "background_image",
+                        image,
+                        egui::TextureOptions::LINEAR,
+                    )),
+                    Err(e) => {
+                        eprintln!("Failed to load background image: {e}.");
+                        None
+                    }
+                }
+            });
+
+            let font_size = effective_font_size(config.font_size, config.min_font_size);
+            let mut session = Session::new(&config, &colors, font_size, initial_pty_size);
+            session.shell = Some(shell);
+
+            Ok(Box::new(TerminalApp {
+                config_modified: modified_time(&config_path),
+                config_polled_at: Instant::now(),
+                config_path,
+                state_path,
+                stdin_buffer,
+                stdin_partial: Vec::new(),
+                stdin_text: String::new(),
+                passthrough,
+                repaint_ctx,
+                shared_reader,
+                sessions: vec![session],
+                active_session: 0,
+                font_size,
+                history: Vec::new(),
+                history_index: None,
+                history_draft: String::new(),
+                input_mode: config.input_mode,
+                pending_focus: Some(focus_target(config.initial_focus, config.input_mode)),
+                active_profile,
+                focused: true,
+                input_rect: None,
+                input_scrollable: false,
+                viewport_width: 0.0,
+                viewport_height: 0.0,
+                palette_dirty_since: None,
+                show_hex_dump: false,
+                show_mirror: config.mirror_output,
+                window_geometry: geometry,
+                window_position_checked: false,
+                process_name_polled_at: Instant::now(),
+                notification: None,
+                notification_pending: false,
+                search_case_sensitive: config.search_case_sensitive,
+                window_title: DEFAULT_TITLE.to_string(),
+                background_image,
+                colors,
+                config,
+            }))
+        }),
+    )
+    .map_err(|e| anyhow::anyhow!("eframe error: {}", e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_session(config: &Config) -> Session {
+        Session::new(config, &Colors::default(), 14.0, PtySize::default())
+    }
+
+    fn test_app(config: Config, config_path: PathBuf) -> TerminalApp {
+        TerminalApp {
+            config_modified: None,
+            config_polled_at: Instant::now(),
+            config_path,
+            state_path: PathBuf::new(),
+            stdin_buffer: None,
+            stdin_partial: Vec::new(),
+            stdin_text: String::new(),
+            passthrough: Vec::new(),
+            repaint_ctx: Arc::new(OnceLock::new()),
+            shared_reader: None,
+            sessions: vec![test_session(&config)],
+            active_session: 0,
+            font_size: 14.0,
+            history: Vec::new(),
+            history_index: None,
+            history_draft: String::new(),
+            input_mode: config.input_mode,
+            pending_focus: None,
+            active_profile: DEFAULT_PROFILE.to_string(),
+            focused: true,
+            input_rect: None,
+            input_scrollable: false,
+            viewport_width: 0.0,
+            viewport_height: 0.0,
+            palette_dirty_since: None,
+            show_hex_dump: false,
+            show_mirror: false,
+            window_geometry: None,
+            window_position_checked: false,
+            process_name_polled_at: Instant::now(),
+            notification: None,
+            notification_pending: false,
+            search_case_sensitive: false,
+            window_title: DEFAULT_TITLE.to_string(),
+            background_image: None,
+            colors: Colors::default(),
+            config,
+        }
+    }
+
+    #[test]
+    fn parse_osc_color_accepts_hex_and_rgb_specs() {
+        assert_eq!(
+            parse_osc_color("#ff8000"),
+            Some(Color32::from_rgb(255, 128, 0))
+        );
+        assert_eq!(
+            parse_osc_color("rgb:ff/80/00"),
+            Some(Color32::from_rgb(255, 128, 0))
+        );
+        assert_eq!(
+            parse_osc_color("rgb:f/8/0"),
+            Some(Color32::from_rgb(255, 136, 0))
+        );
+        assert_eq!(
+            parse_osc_color("rgb:ffff/0000/8080"),
+            Some(Color32::from_rgb(255, 0, 128))
+        );
+    }
+
+    #[test]
+    fn parse_osc_color_rejects_malformed_specs() {
+        for spec in [
+            "",
+            "#",
+            "#ff80",
+            "#ff8000aa",
+            "#a\u{e9}bcd",
+            "#+f8000",
+            "rgb:ff/80",
+            "rgb:ff/80/00/00",
+            "rgb:ff//00",
+            "rgb:+f/80/00",
+            "rgb:fffff/80/00",
+            "rgb:ffffffffffffff/80/00",
+            "red",
+        ] {
+            assert_eq!(parse_osc_color(spec), None, "{spec:?}");
+        }
+    }
+
+    #[test]
+    fn osc_color_changes_update_the_palette_and_mark_it_dirty() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b]4;1;#102030\x07", &config, &mut colors);
+        assert_eq!(colors.red, Color32::from_rgb(16, 32, 48));
+        assert!(mem::take(&mut session.palette_changed));
+        session.append_new_output(b"\x1b]10;rgb:ff/ff/00\x1b\\", &config, &mut colors);
+        assert_eq!(colors.white, Color32::from_rgb(255, 255, 0));
+        assert_eq!(session.current_format.color, colors.white);
+        assert!(mem::take(&mut session.palette_changed));
+        session.append_new_output(b"\x1b]11;#0000", &config, &mut colors);
+        assert!(!session.palette_changed);
+        session.append_new_output(b"ff\x07", &config, &mut colors);
+        assert_eq!(colors.background, Color32::from_rgb(0, 0, 255));
+        assert!(mem::take(&mut session.palette_changed));
+    }
+
+    #[test]
+    fn invalid_osc_colors_leave_the_palette_clean() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            "\x1b]11;#a\u{e9}bcd\x07\x1b]4;1;nope\x07\x1b]4;99;#ffffff\x07".as_bytes(),
+            &config,
+            &mut colors,
+        );
+        assert!(!session.palette_changed);
+        assert_eq!(colors.background, Colors::default().background);
+        assert_eq!(colors.red, Colors::default().red);
+        assert!(session.screen.grid.is_empty());
+    }
+
+    #[test]
+    fn dirty_palette_is_saved_only_after_the_debounce() {
+        let dirty_since = Instant::now();
+        assert!(!should_save_palette(
+            None,
+            dirty_since,
+            PALETTE_SAVE_DEBOUNCE
+        ));
+        assert!(!should_save_palette(
+            Some(dirty_since),
+            dirty_since + PALETTE_SAVE_DEBOUNCE / 2,
+            PALETTE_SAVE_DEBOUNCE,
+        ));
+        assert!(should_save_palette(
+            Some(dirty_since),
+            dirty_since + PALETTE_SAVE_DEBOUNCE,
+            PALETTE_SAVE_DEBOUNCE,
+        ));
+    }
+
+    #[test]
+    fn stderr_is_coloured_apart_from_stdout() {
+        let mut config = Config::default();
+        let colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"out ", &config, &mut Colors::default());
+        session.append_stderr(b"\x1b[32merr\x1b[0m", &config, &colors);
+        session.append_new_output(b" out", &config, &mut Colors::default());
+        let cells = &session.screen.grid.lines[0].cells;
+        let text: String = cells.iter().map(|cell| cell.ch).collect();
+        assert_eq!(text, "out err out");
+        assert!(
+            cells[..4]
+                .iter()
+                .all(|cell| cell.format.color == colors.white)
+        );
+        assert!(
+            cells[4..7]
+                .iter()
+                .all(|cell| cell.format.color == colors.red)
+        );
+        assert!(
+            cells[7..]
+                .iter()
+                .all(|cell| cell.format.color == colors.white)
+        );
+
+        config.stderr_color = Some([1, 2, 3, 255]);
+        session.append_stderr(b"!", &config, &colors);
+        let last = session.screen.grid.lines[0].cells.last().unwrap();
+        assert_eq!(last.format.color, Color32::from_rgb(1, 2, 3));
+    }
+
+    #[test]
+    fn stderr_command_passes_the_command_through() {
+        let fifo = Path::new("/tmp/fifo");
+        let argv = |cmd: CommandBuilder| {
+            cmd.get_argv()
+                .iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(argv(stderr_command("make", &[], fifo))[3..], ["make"]);
+        let with_args = argv(stderr_command("cargo", &["build".to_string()], fifo));
+        assert_eq!(with_args[3..], ["cargo", "build"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stderr_fifos_live_in_private_directories() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        let (first, _) = create_stderr_fifo().unwrap();
+        let (second, _) = create_stderr_fifo().unwrap();
+        let dir = first.path.parent().unwrap().to_path_buf();
+        assert_ne!(dir, second.path.parent().unwrap());
+        assert!(fs::metadata(&first.path).unwrap().file_type().is_fifo());
+        assert_eq!(
+            fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+        drop(first);
+        assert!(!dir.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn separate_stderr_is_read_and_cleaned_up_when_the_command_exits() {
+        let config = Config {
+            separate_stderr: true,
+            ..Config::default()
+        };
+        let passthrough = ["sh", "-c", "echo out; echo err >&2"].map(String::from);
+        let mut shell = spawn_shell(
+            &config,
+            &passthrough,
+            PtySize::default(),
+            Arc::new(OnceLock::new()),
+            None,
+        )
+        .unwrap();
+        let dir = shell
+            ._stderr_fifo
+            .as_ref()
+            .unwrap()
+            .path
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        shell.child.wait().unwrap();
+        let started_at = Instant::now();
+        while shell.stderr_buffer.lock().unwrap().is_empty()
+            && started_at.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*shell.stderr_buffer.lock().unwrap(), b"err\n");
+        drop(shell);
+        assert!(!dir.exists());
+    }
+
+    fn screen_text(session: &Session) -> Vec<String> {
+        let top = session.screen.grid.screen_top(session.screen_rows);
+        session.screen.grid.lines[top..]
+            .iter()
+            .map(Line::text)
+            .collect()
+    }
+
+    #[test]
+    fn clear_screen_scrolls_the_screen_into_scrollback() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"line1\nline2\nprompt$ ", &config, &mut colors);
+        session.append_new_output(b"\x1b[H\x1b[2JX", &config, &mut colors);
+        let screen = screen_text(&session);
+        assert_eq!(screen.len(), session.screen_rows);
+        assert_eq!(screen[0], "X");
+        assert!(screen[1..].iter().all(String::is_empty));
+        assert!(
+            session
+                .screen
+                .grid
+                .text()
+                .starts_with("line1\nline2\nprompt$ \n")
+        );
+    }
+
+    #[test]
+    fn clear_screen_truncate_drops_all_content() {
+        let config = Config {
+            clear_mode: ClearMode::Truncate,
+            ..Config::default()
+        };
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"line1\nline2\x1b[2J", &config, &mut colors);
+        assert!(session.screen.grid.is_empty());
+        session.append_new_output(b"X", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "X");
+    }
+
+    #[test]
+    fn erase_in_line_truncates_from_the_cursor() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"first\nhello\x1b[3D\x1b[K", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "first\nhe");
+        session.append_new_output(b"\x1b[1K", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "first\n  ");
+        session.append_new_output(b"y\r\x1b[2K", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "first\n");
+    }
+
+    fn cell_at(session: &Session, row: usize, column: usize) -> Option<char> {
+        let top = session.screen.grid.screen_top(session.screen_rows);
+        let line = session.screen.grid.lines.get(top + row - 1)?;
+        line.cells.get(column - 1).map(|cell| cell.ch)
+    }
+
+    #[test]
+    fn cursor_position_writes_land_at_the_cursor() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[2;3HX", &config, &mut colors);
+        assert_eq!(cell_at(&session, 2, 3), Some('X'));
+        session.append_new_output(b"\x1b[2;2fY", &config, &mut colors);
+        assert_eq!(screen_text(&session)[1], " YX");
+    }
+
+    #[test]
+    fn relative_cursor_moves_follow_their_parameters() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[5;10H\x1b[3AA", &config, &mut colors);
+        assert_eq!(cell_at(&session, 2, 10), Some('A'));
+        session.append_new_output(b"\x1b[BB", &config, &mut colors);
+        assert_eq!(cell_at(&session, 3, 11), Some('B'));
+        session.append_new_output(b"\x1b[2CC", &config, &mut colors);
+        assert_eq!(cell_at(&session, 3, 14), Some('C'));
+        session.append_new_output(b"\x1b[4DD", &config, &mut colors);
+        assert_eq!(cell_at(&session, 3, 11), Some('D'));
+        session.append_new_output(b"\x1b[7GG", &config, &mut colors);
+        assert_eq!(cell_at(&session, 3, 7), Some('G'));
+    }
+
+    #[test]
+    fn cursor_moves_clamp_to_the_screen() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[999;999HX", &config, &mut colors);
+        let (rows, cols) = (session.screen_rows, session.screen_cols);
+        assert_eq!(cell_at(&session, rows, cols), Some('X'));
+        session.append_new_output(b"\x1b[99A\x1b[99DY", &config, &mut colors);
+        assert_eq!(cell_at(&session, 1, 1), Some('Y'));
+        assert_eq!(cursor_target("H", "0;0", 4, 4), Some((0, 0)));
+        assert_eq!(cursor_target("A", "", 0, 4), Some((0, 4)));
+        assert_eq!(cursor_target("x", "", 0, 0), None);
+    }
+
+    #[test]
+    fn erase_in_display_clears_below_or_above_the_cursor() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            b"\x1b[Haaa\r\nbbb\r\nccc\x1b[2;2H\x1b[J",
+            &config,
+            &mut colors,
+        );
+        assert_eq!(screen_text(&session)[..3], ["aaa", "b", ""]);
+        session.append_new_output(b"\x1b[1J", &config, &mut colors);
+        assert_eq!(screen_text(&session)[..3], ["   ", " ", ""]);
+    }
+
+    fn link_texts(session: &Session) -> Vec<(String, String)> {
+        let text = session.screen.grid.text();
+        session
+            .screen
+            .links
+            .iter()
+            .map(|(range, url)| (text[range.clone()].to_string(), url.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn link_ranges_follow_the_text_after_a_trim() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        for line in 0..5 {
+            let output =
+                format!("line {line} \x1b]8;;https://{line}.example\x07link{line}\x1b]8;;\x07\n");
+            session.append_new_output(output.as_bytes(), &config, &mut colors);
+        }
+        session.drop_old_lines(2, usize::MAX);
+        assert!(session.screen.grid.text().starts_with("line 2 link2\n"));
+        assert_eq!(
+            link_texts(&session),
+            [
+                ("link2".to_string(), "https://2.example".to_string()),
+                ("link3".to_string(), "https://3.example".to_string()),
+                ("link4".to_string(), "https://4.example".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_are_recorded_at_the_cursor() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"first line\nsecond line", &config, &mut colors);
+        session.append_new_output(
+            b"\x1b[1;7H\x1b]8;;https://example.com\x07LINK\x1b]8;;\x07",
+            &config,
+            &mut colors,
+        );
+        assert_eq!(session.screen.grid.text(), "first LINK\nsecond line");
+        assert_eq!(
+            link_texts(&session),
+            [("LINK".to_string(), "https://example.com".to_string())]
+        );
+    }
+
+    #[test]
+    fn osc_titles_never_reach_the_scrollback() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b]0;first title\x07", &config, &mut colors);
+        assert!(session.screen.grid.is_empty());
+        assert_eq!(session.osc_title.as_deref(), Some("first title"));
+        session.append_new_output(b"a\x1b]2;second", &config, &mut colors);
+        session.append_new_output(b" title\x1b\\b", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "ab");
+        assert_eq!(session.title(), "second title");
+    }
+
+    #[test]
+    fn new_tab_expansions_use_the_current_tab_width() {
+        let mut config = Config {
+            tab_width: 4,
+            ..Config::default()
+        };
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"a\tb\n", &config, &mut colors);
+        config.tab_width = 8;
+        session.append_new_output(b"a\tb", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "a   b\na       b");
+    }
+
+    #[test]
+    fn enter_sends_cr_in_both_keypad_modes() {
+        let mut modes = TerminalModes::new(true);
+        for application_keypad in [false, true] {
+            modes.application_keypad = application_keypad;
+            let sequence = encode_key(egui::Key::Enter, egui::Modifiers::NONE, modes);
+            assert_eq!(sequence.as_deref(), Some(&b"\r"[..]));
+        }
+    }
+
+    #[test]
+    fn control_bytes_show_their_caret_chord() {
+        assert_eq!(caret_notation(0x03).as_deref(), Some("^C"));
+        assert_eq!(caret_notation(0x7f).as_deref(), Some("^?"));
+        assert_eq!(caret_notation(b'a'), None);
+        let config = Config {
+            show_control_chords: true,
+            ..Config::default()
+        };
+        let mut session = test_session(&config);
+        session.send_input(b"ls", &config);
+        assert!(session.control_chord.is_none());
+        session.send_input(&[control_byte(egui::Key::D).unwrap()], &config);
+        assert_eq!(
+            session.control_chord.map(|(_, chord)| chord).as_deref(),
+            Some("^D")
+        );
+    }
+
+    #[test]
+    fn sgr_sets_bold_underline_and_colour_until_reset() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[1;4;32mhi\x1b[0mx", &config, &mut colors);
+        let cells = &session.screen.grid.lines[0].cells;
+        for cell in &cells[..2] {
+            assert!(cell.bold);
+            assert_eq!(cell.format.color, colors.green);
+            assert_ne!(cell.format.underline, egui::Stroke::NONE);
+        }
+        assert!(!cells[2].bold);
+        assert_eq!(cells[2].format.color, colors.white);
+        assert_eq!(cells[2].format.underline, egui::Stroke::NONE);
+        let font_id = FontId::monospace(14.0);
+        let job = session.screen.grid.bold_layout_job(&font_id).unwrap();
+        assert_eq!(job.text, "hix");
+        assert_eq!(job.sections[0].format.color, colors.green);
+        assert_eq!(job.sections[1].format.color, Color32::TRANSPARENT);
+    }
+
+    #[test]
+    fn link_targets_cover_links_urls_and_references() {
+        let text = "see https://example.com/a.\nfix #42 in deadbeef1";
+        let issue = Some("https://tracker/{}");
+        let commit = Some("https://repo/commit/{}");
+        let target = |index| link_target(&[], text, index, issue, commit);
+        assert_eq!(
+            target(10),
+            Some((4..25, "https://example.com/a".to_string()))
+        );
+        assert_eq!(target(25), None);
+        assert_eq!(target(32), Some((31..34, "https://tracker/42".to_string())));
+        assert_eq!(
+            target(40),
+            Some((38..47, "https://repo/commit/deadbeef1".to_string()))
+        );
+        assert_eq!(link_target(&[], text, 32, None, None), None);
+        let links = [(31..34, "https://linked".to_string())];
+        assert_eq!(
+            link_target(&links, text, 33, issue, commit),
+            Some((31..34, "https://linked".to_string()))
+        );
+    }
+
+    #[test]
+    fn sessions_enforce_their_own_scrollback_limits() {
+        let config = Config::default();
+        let mut log = test_session(&config);
+        let mut editor = test_session(&config);
+        log.scrollback_lines = 3;
+        editor.scrollback_lines = 5;
+        let output = "line\n".repeat(10);
+        for session in [&mut log, &mut editor] {
+            session.append_new_output(output.as_bytes(), &config, &mut Colors::default());
+            while session.trim_scrollback(usize::MAX) {}
+        }
+        assert_eq!(log.screen.grid.newline_count(), 3);
+        assert_eq!(editor.screen.grid.newline_count(), 5);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_reader_routes_bytes_to_their_sessions() {
+        use std::os::unix::net::UnixStream;
+        let reader = SharedReader::default();
+        let (mut first_writer, first) = UnixStream::pair().unwrap();
+        let (mut second_writer, second) = UnixStream::pair().unwrap();
+        let first_output = Arc::new(Mutex::new(Vec::new()));
+        let second_output = Arc::new(Mutex::new(Vec::new()));
+        let first_finished = reader.add(
+            fs::File::from(std::os::fd::OwnedFd::from(first)),
+            first_output.clone(),
+        );
+        let second_finished = reader.add(
+            fs::File::from(std::os::fd::OwnedFd::from(second)),
+            second_output.clone(),
+        );
+        first_writer.write_all(b"first").unwrap();
+        second_writer.write_all(b"second").unwrap();
+        drop(second_writer);
+        while reader.read_ready(MIN_BUFFERED_OUTPUT, Duration::from_millis(100)) {}
+        assert_eq!(*first_output.lock().unwrap(), b"first");
+        assert_eq!(*second_output.lock().unwrap(), b"second");
+        assert!(!first_finished.load(Ordering::Acquire));
+        assert!(second_finished.load(Ordering::Acquire));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_reader_skips_full_buffers() {
+        use std::os::unix::net::UnixStream;
+        let reader = SharedReader::default();
+        let (mut writer, source) = UnixStream::pair().unwrap();
+        let output = Arc::new(Mutex::new(b"full".to_vec()));
+        reader.add(
+            fs::File::from(std::os::fd::OwnedFd::from(source)),
+            output.clone(),
+        );
+        writer.write_all(b"more").unwrap();
+        assert!(!reader.read_ready(4, Duration::from_millis(10)));
+        output.lock().unwrap().clear();
+        assert!(reader.read_ready(4, Duration::from_millis(10)));
+        assert_eq!(*output.lock().unwrap(), b"more");
+    }
+
+    #[test]
+    fn cursor_visibility_toggles_are_not_a_visible_change() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        assert!(!session.append_new_output(b"\x1b[?25l\x1b[?25h", &config, &mut colors));
+        assert!(session.modes.cursor_visible);
+        assert!(session.append_new_output(b"x", &config, &mut colors));
+    }
+
+    #[test]
+    fn trailing_crlf_waits_for_more_text() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"done\r\n", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "done");
+        assert_eq!(session.screen.pending_newlines, 1);
+        session.append_new_output(b"next", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "done\nnext");
+        assert_eq!(session.screen.pending_newlines, 0);
+    }
+
+    #[test]
+    fn scroll_percentage_is_hidden_at_the_bottom() {
+        assert_eq!(scroll_percentage(0.0, 1000.0, 200.0), Some(0));
+        assert_eq!(scroll_percentage(400.0, 1000.0, 200.0), Some(50));
+        assert_eq!(scroll_percentage(576.0, 1000.0, 200.0), Some(72));
+        assert_eq!(scroll_percentage(800.0, 1000.0, 200.0), None);
+        assert_eq!(scroll_percentage(0.0, 100.0, 200.0), None);
+    }
+
+    #[test]
+    fn input_mode_toggle_switches_routing() {
+        assert_eq!(InputMode::Line.toggled(), InputMode::Raw);
+        assert_eq!(InputMode::Raw.toggled(), InputMode::Line);
+        assert_eq!(
+            focus_target(FocusTarget::Input, InputMode::Raw.toggled()),
+            FocusTarget::Input
+        );
+        assert_eq!(
+            focus_target(FocusTarget::Input, InputMode::Line.toggled()),
+            FocusTarget::Terminal
+        );
+        let events = [
+            egui::Event::Key {
+                key: egui::Key::F12,
+                physical_key: None,
+                pressed: true,
+                repeat: false,
+                modifiers: egui::Modifiers::NONE,
+            },
+            egui::Event::Text("ls".to_string()),
+        ];
+        let modes = TerminalModes::new(true);
+        assert_eq!(
+            raw_input_bytes(&events, Some(egui::Key::F12), modes, false, true),
+            b"ls"
+        );
+    }
+
+    #[test]
+    fn cursor_row_rect_spans_the_cursor_row() {
+        let rect = cursor_row_rect(egui::pos2(10.0, 20.0), 300.0, 3, 16.0);
+        assert_eq!(rect.min, egui::pos2(10.0, 68.0));
+        assert_eq!(rect.size(), egui::vec2(300.0, 16.0));
+    }
+
+    #[test]
+    fn synchronized_output_defers_the_relayout_until_it_ends() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        let mut feed = |session: &mut Session, bytes: &[u8]| {
+            if session.append_new_output(bytes, &config, &mut colors) {
+                session.layout_dirty = true;
+            }
+        };
+        feed(&mut session, b"\x1b[?2026hfirst ");
+        assert!(!session.take_layout_update());
+        feed(&mut session, b"second");
+        assert!(!session.take_layout_update());
+        feed(&mut session, b"\x1b[?2026l");
+        assert!(session.take_layout_update());
+        assert_eq!(session.screen.grid.text(), "first second");
+        assert!(!session.take_layout_update());
+    }
+
+    #[test]
+    fn oversized_osc_payloads_are_dropped() {
+        let config = Config {
+            max_osc_payload: 16,
+            ..Config::default()
+        };
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        let mut output = b"\x1b]0;".to_vec();
+        output.extend(std::iter::repeat_n(b'A', 4096));
+        output.extend_from_slice(b"\x07after");
+        session.append_new_output(&output, &config, &mut colors);
+        assert_eq!(session.osc_title, None);
+        assert!(session.osc.is_none());
+        assert_eq!(session.screen.grid.text(), "after");
+        session.append_new_output(b"\x1b]0;short\x07", &config, &mut colors);
+        assert_eq!(session.osc_title.as_deref(), Some("short"));
+    }
+
+    #[test]
+    fn find_references_builds_issue_and_commit_urls() {
+        let references = find_references(
+            "closes #12, see a1b2c3d and #x or deadbeefcafe0 abcdefg",
+            Some("https://tracker/issues/{}"),
+            Some("https://repo/commit/{}"),
+        );
+        assert_eq!(
+            references,
+            [
+                (7..10, "https://tracker/issues/12".to_string()),
+                (16..23, "https://repo/commit/a1b2c3d".to_string()),
+                (34..47, "https://repo/commit/deadbeefcafe0".to_string()),
+            ]
+        );
+        assert!(find_references("#12 a1b2c3d", None, None).is_empty());
+    }
+
+    #[test]
+    fn background_image_paths_and_opacity_are_validated() {
+        let dir = std::env::temp_dir();
+        let image = dir.join(format!("yate-test-{}.PNG", std::process::id()));
+        let text = dir.join(format!("yate-test-{}.txt", std::process::id()));
+        fs::write(&image, b"").unwrap();
+        fs::write(&text, b"").unwrap();
+        assert_eq!(
+            validate_background_image(image.to_str().unwrap()).unwrap(),
+            image
+        );
+        assert!(validate_background_image(text.to_str().unwrap()).is_err());
+        assert!(validate_background_image(dir.to_str().unwrap()).is_err());
+        assert!(validate_background_image("/nonexistent/wallpaper.png").is_err());
+        fs::remove_file(image).unwrap();
+        fs::remove_file(text).unwrap();
+        assert_eq!(clamp_opacity(0.3), 0.3);
+        assert_eq!(clamp_opacity(-1.0), 0.0);
+        assert_eq!(clamp_opacity(4.0), 1.0);
+        assert_eq!(clamp_opacity(f32::NAN), 1.0);
+    }
+
+    #[test]
+    fn active_profile_round_trips_through_the_state_file() {
+        let path = std::env::temp_dir().join(format!("yate-state-{}.toml", std::process::id()));
+        let state = State {
+            active_profile: Some("light".to_string()),
+            window: None,
+        };
+        save_state(&path, &state).unwrap();
+        let loaded = load_state(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.active_profile.as_deref(), Some("light"));
+        let palette = ColorPalette::from(&Colors::default());
+        let profiles = BTreeMap::from([
+            (DEFAULT_PROFILE.to_string(), palette.clone()),
+            ("light".to_string(), palette),
+        ]);
+        assert_eq!(
+            resolve_profile(loaded.active_profile.as_deref(), &profiles),
+            "light"
+        );
+        assert_eq!(resolve_profile(Some("removed"), &profiles), DEFAULT_PROFILE);
+        assert_eq!(resolve_profile(None, &profiles), DEFAULT_PROFILE);
+    }
+
+    #[test]
+    fn identical_formats_coalesce_into_one_run() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            b"\x1b[31ma\x1b[0m\x1b[0m\x1b[31mb\x1b[31m\x1b[31mc",
+            &config,
+            &mut colors,
+        );
+        let job = session.screen.grid.layout_job(&FontId::monospace(14.0));
+        assert_eq!(job.text, "abc");
+        assert_eq!(job.sections.len(), 1);
+        assert_eq!(job.sections[0].format.color, colors.red);
+        let cells = &session.screen.grid.lines[0].cells;
+        assert!(Arc::ptr_eq(&cells[1].format, &cells[2].format));
+    }
+
+    #[test]
+    fn session_titles_prefer_osc_then_command_then_process() {
+        assert_eq!(resolve_title(Some("osc"), Some("cmd"), Some("bash")), "osc");
+        assert_eq!(resolve_title(Some("  "), Some("cmd"), Some("bash")), "cmd");
+        assert_eq!(resolve_title(None, None, Some("bash")), "bash");
+        assert_eq!(resolve_title(None, None, None), DEFAULT_TITLE);
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        assert_eq!(session.title(), DEFAULT_TITLE);
+        session.process_name = Some("vim".to_string());
+        assert_eq!(session.title(), "vim");
+        session.append_new_output(b"\x1b]2;notes\x07", &config, &mut colors);
+        assert_eq!(session.title(), "notes");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn process_names_resolve_from_a_mock_proc_tree() {
+        let root = std::env::temp_dir().join(format!("yate-proc-{}", std::process::id()));
+        fs::create_dir_all(root.join("42")).unwrap();
+        fs::create_dir_all(root.join("43")).unwrap();
+        fs::write(root.join("42").join("comm"), "vim\n").unwrap();
+        fs::write(root.join("43").join("comm"), "\n").unwrap();
+        assert_eq!(process_name(&root, 42).as_deref(), Some("vim"));
+        assert_eq!(process_name(&root, 43), None);
+        assert_eq!(process_name(&root, 44), None);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn horizontal_offset_clamps_to_the_overflow() {
+        assert_eq!(clamp_horizontal_offset(50.0, 800.0, 600.0), 50.0);
+        assert_eq!(clamp_horizontal_offset(-10.0, 800.0, 600.0), 0.0);
+        assert_eq!(clamp_horizontal_offset(500.0, 800.0, 600.0), 200.0);
+        assert_eq!(clamp_horizontal_offset(30.0, 400.0, 600.0), 0.0);
+    }
+
+    #[test]
+    fn prompt_start_marks_trigger_the_flash() {
+        assert_eq!(parse_shell_mark("A"), Some(ShellMark::PromptStart));
+        assert_eq!(parse_shell_mark("A;k=i"), Some(ShellMark::PromptStart));
+        assert_eq!(
+            parse_shell_mark("D;1"),
+            Some(ShellMark::CommandEnd(Some(1)))
+        );
+        assert_eq!(parse_shell_mark("E"), None);
+        let config = Config {
+            flash_prompt: true,
+            ..Config::default()
+        };
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"out\n\x1b]133;B\x07", &config, &mut colors);
+        assert!(session.prompt_flash.is_none());
+        session.append_new_output(b"\x1b]133;A\x07$ ", &config, &mut colors);
+        assert_eq!(session.prompt_flash.map(|(_, index)| index), Some(4));
+    }
+
+    #[test]
+    fn strip_ansi_removes_escapes_and_keeps_text() {
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m plain"), "red plain");
+        assert_eq!(strip_ansi("a\x1b[2Kb\x1b[?25lc"), "abc");
+        assert_eq!(strip_ansi("\x1b]0;title\x07text"), "text");
+        assert_eq!(
+            strip_ansi("\x1b]8;;https://x\x1b\\link\x1b]8;;\x1b\\"),
+            "link"
+        );
+        assert_eq!(strip_ansi("ünïcode\ttab\n"), "ünïcode\ttab\n");
+    }
+
+    #[test]
+    fn hex_dump_shows_offset_hex_and_ascii_columns() {
+        let dump = hex_dump(b"hello, world!\n\x00\xffmore", 0x20);
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "00000020  68 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |hello, world!...|",
+                "00000030  6d 6f 72 65                                       |more|",
+            ]
+        );
+        assert_eq!(hex_dump(b"", 0), "");
+    }
+
+    #[test]
+    fn clear_modes_keep_or_discard_the_scrollback() {
+        for (clear_mode, kept) in [(ClearMode::Scroll, true), (ClearMode::Truncate, false)] {
+            let config = Config {
+                clear_mode,
+                ..Config::default()
+            };
+            let mut colors = Colors::default();
+            let mut session = test_session(&config);
+            session.append_new_output(
+                b"old output\n$ clear\n\x1b[H\x1b[2J$ ",
+                &config,
+                &mut colors,
+            );
+            assert_eq!(session.screen.grid.text().contains("old output"), kept);
+            assert_eq!(screen_text(&session)[0], "$ ");
+        }
+    }
+
+    #[test]
+    fn initial_focus_follows_the_input_mode() {
+        for preferred in [FocusTarget::Input, FocusTarget::Terminal] {
+            assert_eq!(focus_target(preferred, InputMode::Line), preferred);
+            assert_eq!(
+                focus_target(preferred, InputMode::Raw),
+                FocusTarget::Terminal
+            );
+        }
+    }
+
+    #[test]
+    fn repaint_interval_backs_off_only_when_idle_and_unfocused() {
+        let unfocused = Duration::from_millis(250);
+        let fast = Duration::from_millis(1);
+        assert_eq!(repaint_interval(true, false, unfocused), fast);
+        assert_eq!(repaint_interval(false, true, unfocused), fast);
+        assert_eq!(repaint_interval(false, false, unfocused), unfocused);
+    }
+
+    #[test]
+    fn command_marks_record_duration_and_exit_code() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b]133;C\x07", &config, &mut colors);
+        std::thread::sleep(Duration::from_millis(20));
+        session.append_new_output(b"output\n\x1b]133;D;2\x07", &config, &mut colors);
+        let result = session.last_command.as_ref().unwrap();
+        assert_eq!(result.exit_code, Some(2));
+        assert!(result.duration >= Duration::from_millis(20));
+        assert!(session.command_started_at.is_none());
+        assert_eq!(
+            format_command_result(Duration::from_millis(1500), Some(0)),
+            "ok · 1.50s"
+        );
+        assert_eq!(
+            format_command_result(Duration::from_secs(125), Some(1)),
+            "exit 1 · 2m05s"
+        );
+        assert_eq!(format_command_result(Duration::from_secs(3), None), "3.00s");
+    }
+
+    #[test]
+    fn soft_reset_restores_modes_and_keeps_the_text() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            b"\x1b[?25l\x1b[?7l\x1b[?1h\x1b=\x1b[?2004h\x1b[1;31mkept",
+            &config,
+            &mut colors,
+        );
+        session.append_new_output(b"\x1b[!p", &config, &mut colors);
+        assert_eq!(session.modes, TerminalModes::new(config.wrap_lines));
+        assert!(!session.bold);
+        assert_eq!(session.current_format.color, colors.white);
+        assert_eq!(session.screen.grid.text(), "kept");
+        assert_eq!(
+            session.screen.grid.lines[0].cells[0].format.color,
+            colors.red
+        );
+    }
+
+    #[test]
+    fn unprintable_policies_replace_or_drop_control_characters() {
+        let input = "a\x01b\u{fffd}c\td\n";
+        assert_eq!(
+            replace_unprintable(input, UnprintablePolicy::Placeholder),
+            "a·b·c\td\n"
+        );
+        assert_eq!(
+            replace_unprintable(input, UnprintablePolicy::Hex),
+            "a<01>b<?>c\td\n"
+        );
+        assert_eq!(
+            replace_unprintable(input, UnprintablePolicy::Drop),
+            "abc\td\n"
+        );
+    }
+
+    #[test]
+    fn command_output_is_extracted_between_markers() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            "λ ls\n\x1b]133;C\x07out1\nout2\n\x1b]133;D;0\x07$ pwd\n\x1b]133;C\x07/home\n\x1b]133;D;0\x07$ ".as_bytes(),
+            &config,
+            &mut colors,
+        );
+        let text = session.screen.grid.text();
+        let output_at = |line: &str| {
+            let position = text[..text.find(line).unwrap()].chars().count();
+            command_region_at(&session.screen.command_regions, position).map(|range| {
+                text.chars()
+                    .skip(range.start)
+                    .take(range.len())
+                    .collect::<String>()
+            })
+        };
+        assert_eq!(output_at("out2").as_deref(), Some("out1\nout2\n"));
+        assert_eq!(output_at("/home").as_deref(), Some("/home\n"));
+        assert_eq!(output_at("λ ls"), None);
+    }
+
+    #[test]
+    fn wheel_scrolls_the_input_only_when_it_can_scroll() {
+        assert_eq!(wheel_target(true, true), WheelTarget::Input);
+        assert_eq!(wheel_target(true, false), WheelTarget::Terminal);
+        assert_eq!(wheel_target(false, true), WheelTarget::Terminal);
+        assert_eq!(wheel_target(false, false), WheelTarget::Terminal);
+    }
+
+    #[test]
+    fn composed_text_is_dispatched_apart_from_key_presses() {
+        let key = |pressed| egui::Event::Key {
+            key: egui::Key::E,
+            physical_key: None,
+            pressed,
+            repeat: false,
+            modifiers: egui::Modifiers::NONE,
+        };
+        let composed = egui::Event::Text("é".to_string());
+        let ime = egui::Event::Ime(egui::ImeEvent::Commit("ñ".to_string()));
+        assert_eq!(classify_event(&composed), Some(InputEvent::Text("é")));
+        assert_eq!(classify_event(&ime), Some(InputEvent::Text("ñ")));
+        assert_eq!(
+            classify_event(&key(true)),
+            Some(InputEvent::Key(egui::Key::E, egui::Modifiers::NONE))
+        );
+        assert_eq!(classify_event(&key(false)), None);
+        let modes = TerminalModes::new(true);
+        let events = [key(true), composed, key(false)];
+        assert_eq!(
+            raw_input_bytes(&events, None, modes, false, true),
+            "é".as_bytes()
+        );
+    }
+
+    #[test]
+    fn mode_status_lists_the_active_modes() {
+        let mut modes = TerminalModes::new(true);
+        assert_eq!(mode_status(modes), "normal");
+        modes.alt_screen = true;
+        modes.application_cursor = true;
+        modes.bracketed_paste = true;
+        modes.autowrap = false;
+        assert_eq!(
+            mode_status(modes),
+            "alt-screen | app-cursor | bracketed-paste | no-wrap"
+        );
+    }
+
+    #[test]
+    fn focus_reporting_mode_selects_focus_event_bytes() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[?1004h", &config, &mut colors);
+        assert!(session.modes.focus_reporting);
+        assert_eq!(focus_event_bytes(true), b"\x1b[I");
+        assert_eq!(focus_event_bytes(false), b"\x1b[O");
+        session.append_new_output(b"\x1b[?1004l", &config, &mut colors);
+        assert!(!session.modes.focus_reporting);
+    }
+
+    #[test]
+    fn fixed_grid_is_centred_and_letterboxed() {
+        let available = egui::Rect::from_min_size(egui::pos2(0.0, 20.0), egui::vec2(1000.0, 600.0));
+        let rect = centered_grid_rect(available, egui::vec2(800.0, 400.0));
+        assert_eq!(
+            rect,
+            egui::Rect::from_min_size(egui::pos2(100.0, 120.0), egui::vec2(800.0, 400.0))
+        );
+        let rect = centered_grid_rect(available, egui::vec2(1200.0, 400.0));
+        assert_eq!(rect.width(), 1000.0);
+        assert_eq!(rect.center(), available.center());
+    }
+
+    #[test]
+    fn cursor_is_drawn_only_inside_the_viewport() {
+        assert!(cursor_in_viewport(10, 5, 20));
+        assert!(cursor_in_viewport(5, 5, 20));
+        assert!(!cursor_in_viewport(25, 5, 20));
+        assert!(!cursor_in_viewport(4, 5, 20));
+    }
+
+    #[test]
+    fn translucent_backgrounds_composite_over_the_base() {
+        let base = Color32::from_rgb(0, 0, 0);
+        let half_red = Color32::from_rgba_unmultiplied(255, 0, 0, 128);
+        let blended = composite_over(half_red, base);
+        assert_eq!(blended.to_srgba_unmultiplied(), [128, 0, 0, 255]);
+        let opaque = Color32::from_rgb(10, 20, 30);
+        assert_eq!(composite_over(opaque, base), opaque);
+        assert_eq!(composite_over(Color32::TRANSPARENT, base), base);
+        assert_eq!(
+            composite_over(Color32::TRANSPARENT, Color32::TRANSPARENT),
+            Color32::TRANSPARENT
+        );
+    }
+
+    #[test]
+    fn command_titles_come_from_the_prompt_line() {
+        assert_eq!(
+            title_from_command_line("user@host:~$ vim notes.txt").as_deref(),
+            Some("vim notes.txt")
+        );
+        assert_eq!(
+            title_from_command_line("# sudo RUST_LOG=debug /usr/bin/cargo build --release")
+                .as_deref(),
+            Some("cargo build")
+        );
+        assert_eq!(title_from_command_line("> htop").as_deref(), Some("htop"));
+        assert_eq!(title_from_command_line("make").as_deref(), Some("make"));
+        assert_eq!(title_from_command_line("$ "), None);
+    }
+
+    #[test]
+    fn trimming_is_bounded_per_call_and_reaches_the_cap() {
+        let config = Config {
+            scrollback_lines: 10,
+            ..Config::default()
+        };
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        let output: String = (0..50).map(|line| format!("line {line}\n")).collect();
+        session.append_new_output(output.as_bytes(), &config, &mut colors);
+        let mut calls = 0;
+        loop {
+            let before = session.screen.grid.newline_count();
+            let more = session.trim_scrollback(15);
+            assert!(before - session.screen.grid.newline_count() <= 15);
+            calls += 1;
+            if !more {
+                break;
+            }
+        }
+        assert_eq!(calls, 3);
+        assert_eq!(session.screen.grid.newline_count(), 10);
+        assert!(session.screen.grid.text().starts_with("line 39\n"));
+    }
+
+    #[test]
+    fn kitty_keyboard_requests_and_key_encoding() {
+        assert_eq!(
+            parse_kitty_keyboard(">1"),
+            Some(KittyKeyboardRequest::Push(1))
+        );
+        assert_eq!(
+            parse_kitty_keyboard("<"),
+            Some(KittyKeyboardRequest::Pop(1))
+        );
+        assert_eq!(
+            parse_kitty_keyboard("<3"),
+            Some(KittyKeyboardRequest::Pop(3))
+        );
+        assert_eq!(
+            parse_kitty_keyboard("=5;2"),
+            Some(KittyKeyboardRequest::Set(5, 2))
+        );
+        assert_eq!(parse_kitty_keyboard("?"), Some(KittyKeyboardRequest::Query));
+        assert_eq!(parse_kitty_keyboard("5"), None);
+        let encode = |key, modifiers| encode_kitty_key(key, modifiers).map(String::from_utf8);
+        assert_eq!(
+            encode(egui::Key::C, egui::Modifiers::CTRL),
+            Some(Ok("\x1b[99;5u".to_string()))
+        );
+        assert_eq!(
+            encode(egui::Key::Escape, egui::Modifiers::NONE),
+            Some(Ok("\x1b[27u".to_string()))
+        );
+        assert_eq!(
+            encode(egui::Key::Enter, egui::Modifiers::SHIFT),
+            Some(Ok("\x1b[13;2u".to_string()))
+        );
+        assert_eq!(
+            encode(egui::Key::ArrowUp, egui::Modifiers::ALT),
+            Some(Ok("\x1b[1;3A".to_string()))
+        );
+        assert_eq!(encode(egui::Key::Enter, egui::Modifiers::NONE), None);
+        let config = Config {
+            kitty_keyboard: true,
+            ..Config::default()
+        };
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[>1u\x1b[?u", &config, &mut colors);
+        assert_eq!(session.modes.kitty_keyboard_flags, 1);
+        assert_eq!(session.replies, b"\x1b[?1u");
+        session.append_new_output(b"\x1b[<u", &config, &mut colors);
+        assert_eq!(session.modes.kitty_keyboard_flags, 0);
+    }
+
+    #[test]
+    fn incremental_search_selects_the_first_match() {
+        let text = "Error: one\nwarning\nerror: two";
+        let matches = search(text, "error", false);
+        assert_eq!(matches, [0..5, 19..24]);
+        assert_eq!(search(text, "error", true), matches[1..]);
+        assert!(search(text, "a.b", false).is_empty());
+        assert!(search(text, "", false).is_empty());
+        assert_eq!(step_match(None, matches.len(), true), Some(0));
+        assert_eq!(step_match(Some(1), matches.len(), true), Some(0));
+        assert_eq!(step_match(Some(0), matches.len(), false), Some(1));
+        assert_eq!(step_match(None, 0, true), None);
+    }
+
+    #[test]
+    fn clear_scrollback_keeps_the_screen() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        let output: String = (0..40).map(|line| format!("line {line}\n")).collect();
+        session.append_new_output(output.as_bytes(), &config, &mut colors);
+        session.append_new_output(b"$ ", &config, &mut colors);
+        let screen = screen_text(&session);
+        session.append_new_output(b"\x1b[3J", &config, &mut colors);
+        assert_eq!(screen_text(&session), screen);
+        assert_eq!(session.screen.grid.lines.len(), session.screen_rows);
+        assert_eq!(session.screen.grid.text().lines().last(), Some("$ "));
+    }
+
+    #[test]
+    fn prompt_regions_span_the_prompt_text() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            b"out\n\x1b]133;A\x07user $ \x1b]133;B\x07ls\n\x1b]133;C\x07a\n\x1b]133;A\x07> \x1b]133;C\x07",
+            &config,
+            &mut colors,
+        );
+        let text = session.screen.grid.text();
+        let prompts: Vec<String> = session
+            .screen
+            .prompt_regions
+            .iter()
+            .map(|region| text.chars().skip(region.start).take(region.len()).collect())
+            .collect();
+        assert_eq!(prompts, ["user $ ", "> "]);
+    }
+
+    #[test]
+    fn decrqss_queries_are_answered_with_dcs_replies() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[1;31m\x1bP$qm\x1b\\", &config, &mut colors);
+        assert_eq!(session.replies, b"\x1bP1$r0;1;31m\x1b\\");
+        session.replies.clear();
+        session.append_new_output(b"\x1bP$qr\x1b\\\x1bP$qx\x1b\\", &config, &mut colors);
+        let expected = format!("\x1bP1$r1;{}r\x1b\\\x1bP0$r\x1b\\", session.screen_rows);
+        assert_eq!(session.replies, expected.as_bytes());
+        assert!(session.screen.grid.is_empty());
+    }
+
+    #[test]
+    fn window_size_queries_report_the_text_area() {
+        assert_eq!(
+            window_size_report(18, 24, 80).as_deref(),
+            Some("\x1b[8;24;80t")
+        );
+        assert_eq!(
+            window_size_report(19, 30, 100).as_deref(),
+            Some("\x1b[9;30;100t")
+        );
+        assert_eq!(window_size_report(14, 24, 80), None);
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[18t", &config, &mut colors);
+        let expected = format!("\x1b[8;{};{}t", session.screen_rows, session.screen_cols);
+        assert_eq!(session.replies, expected.as_bytes());
+        session.replies.clear();
+        session.append_new_output(
+            b"\x1b]2;shell\x07\x1b[22;2t\x1b]2;vim\x07",
+            &config,
+            &mut colors,
+        );
+        assert_eq!(session.title(), "vim");
+        session.append_new_output(b"\x1b[23;2t", &config, &mut colors);
+        assert_eq!(session.title(), "shell");
+        assert!(session.replies.is_empty());
+    }
+
+    #[test]
+    fn kinetic_scrolling_decays_to_a_stop() {
+        let mut velocity = 10.0;
+        let mut offsets = Vec::new();
+        let mut offset = 0.0;
+        for _ in 0..6 {
+            let (scroll, next) = kinetic_step(velocity, 0.0, 0.5);
+            offset += scroll;
+            offsets.push(offset);
+            velocity = next;
+        }
+        assert_eq!(offsets, [10.0, 15.0, 17.5, 18.75, 19.375, 19.375]);
+        assert_eq!(kinetic_step(4.0, 7.0, 0.5), (7.0, 7.0));
+        assert_eq!(kinetic_step(100.0, 0.0, 5.0).1, 99.0);
+    }
+
+    #[test]
+    fn title_stack_is_bounded_and_restores_in_order() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        for index in 0..TITLE_STACK_LIMIT + 2 {
+            let output = format!("\x1b]0;title {index}\x07\x1b[22t");
+            session.append_new_output(output.as_bytes(), &config, &mut colors);
+        }
+        assert_eq!(session.title_stack.len(), TITLE_STACK_LIMIT);
+        session.append_new_output(b"\x1b]0;top\x07\x1b[23;0t", &config, &mut colors);
+        assert_eq!(session.title(), format!("title {}", TITLE_STACK_LIMIT + 1));
+        session.append_new_output(b"\x1b[23t", &config, &mut colors);
+        assert_eq!(session.title(), format!("title {TITLE_STACK_LIMIT}"));
+        session.append_new_output(b"\x1b[22;1t", &config, &mut colors);
+        assert_eq!(session.title_stack.len(), TITLE_STACK_LIMIT - 2);
+
+        let config = Config {
+            title_stack: false,
+            ..Config::default()
+        };
+        let mut session = test_session(&config);
+        session.append_new_output(
+            b"\x1b]0;a\x07\x1b[22t\x1b]0;b\x07\x1b[23t",
+            &config,
+            &mut colors,
+        );
+        assert_eq!(session.title(), "b");
+    }
+
+    #[test]
+    fn enq_sends_the_answerback_without_output() {
+        let config = Config {
+            answerback: "YATE".to_string(),
+            ..Config::default()
+        };
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        assert!(!session.append_new_output(b"\x05", &config, &mut colors));
+        assert_eq!(session.replies, b"YATE");
+        assert!(session.screen.grid.is_empty());
+        session.append_new_output(b"a\x05b", &config, &mut colors);
+        assert_eq!(session.screen.grid.text(), "ab");
+    }
+
+    #[test]
+    fn palette_colors_map_to_their_names() {
+        let colors = Colors::default();
+        assert_eq!(palette_color_name(&colors, colors.red), Some("red"));
+        assert_eq!(
+            palette_color_name(&colors, colors.bright_blue),
+            Some("bright_blue")
+        );
+        assert_eq!(palette_color_name(&colors, colors.white), Some("white"));
+        assert_eq!(
+            palette_color_name(&colors, Color32::from_rgb(1, 2, 3)),
+            None
+        );
+    }
+
+    #[test]
+    fn escape_sequences_survive_split_reads() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"a\x1b[3", &config, &mut colors);
+        session.append_new_output(b"1mred\x1b", &config, &mut colors);
+        session.append_new_output(b"[0mplain", &config, &mut colors);
+        let job = session.screen.grid.layout_job(&FontId::monospace(14.0));
+        assert_eq!(job.text, "aredplain");
+        let red: Vec<_> = job
+            .sections
+            .iter()
+            .filter(|section| section.format.color == colors.red)
+            .map(|section| &job.text[section.byte_range.clone()])
+            .collect();
+        assert_eq!(red, ["red"]);
+    }
+
+    #[test]
+    fn extended_sgr_colours_resolve_exactly() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[38;5;196ma", &config, &mut colors);
+        session.append_new_output(b"\x1b[38;2;10;20;30;48;5;244mb", &config, &mut colors);
+        let cells = &session.screen.grid.lines[0].cells;
+        assert_eq!(cells[0].format.color, Color32::from_rgb(255, 0, 0));
+        assert_eq!(cells[1].format.color, Color32::from_rgb(10, 20, 30));
+        assert_eq!(cells[1].format.background, Color32::from_rgb(128, 128, 128));
+        assert_eq!(color_256(&colors, 1), colors.red);
+        assert_eq!(color_256(&colors, 16), Color32::from_rgb(0, 0, 0));
+        assert_eq!(color_256(&colors, 232), Color32::from_rgb(8, 8, 8));
+        assert_eq!(color_256(&colors, 255), Color32::from_rgb(238, 238, 238));
+    }
+
+    #[test]
+    fn links_spanning_rows_resolve_from_either_segment() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            b"see \x1b]8;;https://example.com/long\x07first half\nsecond half\x1b]8;;\x07 done",
+            &config,
+            &mut colors,
+        );
+        let text = session.screen.grid.text();
+        let target = |needle: &str| {
+            link_target(
+                &session.screen.links,
+                &text,
+                text.find(needle).unwrap(),
+                None,
+                None,
+            )
+            .map(|(_, url)| url)
+        };
+        let url = Some("https://example.com/long".to_string());
+        assert_eq!(target("first"), url);
+        assert_eq!(target("second"), url);
+        assert_eq!(target("done"), None);
+        assert_eq!(session.screen.links.len(), 1);
+    }
+
+    #[test]
+    fn input_limit_truncates_on_char_boundaries() {
+        let mut text = "héllo wörld".to_string();
+        assert!(!enforce_input_limit(&mut text, None));
+        assert!(!enforce_input_limit(&mut text, Some(12)));
+        assert!(enforce_input_limit(&mut text, Some(11)));
+        assert_eq!(text, "héllo wörld");
+        assert!(enforce_input_limit(&mut text, Some(7)));
+        assert_eq!(text, "héllo w");
+        assert!(enforce_input_limit(&mut text, Some(0)));
+        assert_eq!(text, "");
+    }
+
+    #[test]
+    fn history_steps_through_entries_and_back_to_the_draft() {
+        assert_eq!(history_step(0, None, true), None);
+        assert_eq!(history_step(3, None, true), Some(2));
+        assert_eq!(history_step(3, Some(2), true), Some(1));
+        assert_eq!(history_step(3, Some(0), true), Some(0));
+        assert_eq!(history_step(3, Some(1), false), Some(2));
+        assert_eq!(history_step(3, Some(2), false), None);
+        assert_eq!(history_step(3, None, false), None);
+    }
+
+    #[test]
+    fn underline_styles_parse_and_select_their_shapes() {
+        assert_eq!(parse_underline_style("0"), UnderlineStyle::None);
+        assert_eq!(parse_underline_style("1"), UnderlineStyle::Straight);
+        assert_eq!(parse_underline_style("3"), UnderlineStyle::Curly);
+        assert_eq!(parse_underline_style("5"), UnderlineStyle::Dashed);
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[4:3mwavy\x1b[4:0m flat", &config, &mut colors);
+        assert_eq!(session.screen.underline_spans.len(), 1);
+        let (range, style, _) = &session.screen.underline_spans[0];
+        assert_eq!((range.clone(), *style), (0..4, UnderlineStyle::Curly));
+        assert_eq!(
+            session.screen.grid.lines[0].cells[0].format.underline,
+            egui::Stroke::NONE
+        );
+        let rect = egui::Rect::from_min_size(egui::pos2(0.0, 0.0), egui::vec2(40.0, 16.0));
+        let shapes = |style| underline_shapes(rect, style, Color32::WHITE).len();
+        assert_eq!(shapes(UnderlineStyle::None), 0);
+        assert_eq!(shapes(UnderlineStyle::Straight), 1);
+        assert_eq!(shapes(UnderlineStyle::Double), 2);
+        assert!(shapes(UnderlineStyle::Dotted) > shapes(UnderlineStyle::Dashed));
+    }
+
+    #[test]
+    fn tiny_fonts_still_give_a_one_cell_grid() {
+        assert_eq!(effective_font_size(0.0, 4.0), 4.0);
+        assert_eq!(effective_font_size(f32::NAN, 0.0), 1.0);
+        assert_eq!(effective_font_size(14.0, 4.0), 14.0);
+        let area = egui::vec2(800.0, 600.0);
+        assert_eq!(grid_dimensions(area, 0.0, 1e-9), (1, 1));
+        assert_eq!(grid_dimensions(egui::vec2(2.0, 3.0), 8.0, 16.0), (1, 1));
+        assert_eq!(
+            grid_dimensions(egui::vec2(f32::INFINITY, 32.0), 8.0, 16.0),
+            (2, 1)
+        );
+        assert_eq!(grid_dimensions(area, 8.0, 16.0), (37, 100));
+    }
+
+    #[test]
+    fn copy_interrupts_only_without_selection_or_shift() {
+        assert!(copy_sends_interrupt(false, false));
+        assert!(!copy_sends_interrupt(false, true));
+        assert!(!copy_sends_interrupt(true, false));
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[1;31mred\x1b[0m plain", &config, &mut colors);
+        assert_eq!(screen_text(&session), ["red plain"]);
+    }
+
+    #[test]
+    fn control_byte_masks_letters_only() {
+        assert_eq!(control_byte(egui::Key::C), Some(0x03));
+        assert_eq!(control_byte(egui::Key::D), Some(0x04));
+        assert_eq!(control_byte(egui::Key::Z), Some(0x1a));
+        assert_eq!(control_byte(egui::Key::F1), None);
+        assert_eq!(control_byte(egui::Key::Num1), None);
+        let bytes = encode_key(
+            egui::Key::C,
+            egui::Modifiers::CTRL,
+            TerminalModes::new(true),
+        );
+        assert_eq!(bytes.as_deref(), Some(&[0x03][..]));
+    }
+
+    #[test]
+    fn rewritten_progress_lines_export_their_final_frame() {
+        let frames = "start\n 10%\r 50%\r100%\r\ndone";
+        assert_eq!(collapse_rewrites(frames), "start\n100%\ndone");
+        assert_eq!(collapse_rewrites("plain\ntext"), "plain\ntext");
+        assert_eq!(collapse_rewrites("a\r"), "a");
+    }
+
+    #[test]
+    fn config_files_live_in_the_user_config_dir() {
+        let path = config_file_path("yate-missing-test.toml", false);
+        if let Some(dir) = dirs::config_dir() {
+            assert_eq!(path, dir.join("YATE").join("yate-missing-test.toml"));
+        } else {
+            assert_eq!(path, PathBuf::from("yate-missing-test.toml"));
+        }
+        let path = std::env::temp_dir().join(format!("yate-colors-{}.toml", std::process::id()));
+        let colors = Colors {
+            red: Color32::from_rgb(1, 2, 3),
+            ..Colors::default()
+        };
+        save_colors(&path, &ColorPalette::from(&colors)).unwrap();
+        let loaded = load_colors(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().red, [1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn notify_patterns_match_whole_completed_lines() {
+        let config = Config {
+            notify_patterns: vec!["FAILED".to_string(), "(".to_string()],
+            ..Config::default()
+        };
+        let patterns = compile_patterns(&config.notify_patterns);
+        assert_eq!(patterns.len(), 1);
+        assert!(should_notify("test FAILED", &patterns));
+        assert!(!should_notify("ok", &patterns));
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"build FAI", &config, &mut colors);
+        assert!(session.notification.is_none());
+        session.append_new_output(b"LED\r\nnext", &config, &mut colors);
+        assert_eq!(session.notification.as_deref(), Some("build FAILED"));
+    }
+
+    #[test]
+    fn passthrough_overrides_the_configured_shell() {
+        let shell: ShellConfig = toml::from_str("program = \"zsh\"\nargs = [\"-l\"]").unwrap();
+        assert_eq!(
+            shell_invocation(&shell, &[]),
+            ("zsh".to_string(), vec!["-l".to_string()])
+        );
+        let passthrough = ["bash".to_string(), "-l".to_string()];
+        assert_eq!(
+            shell_invocation(&shell, &passthrough),
+            ("bash".to_string(), vec!["-l".to_string()])
+        );
+        let (program, args) = shell_invocation(&ShellConfig::default(), &[]);
+        assert_eq!(program, default_shell());
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn block_cursor_glyph_uses_the_cursor_text_colour() {
+        let colors = Colors::default();
+        assert_eq!(
+            cursor_glyph_color(None, colors.background),
+            colors.background
+        );
+        assert_eq!(
+            cursor_glyph_color(Some([10, 20, 30, 255]), colors.background),
+            Color32::from_rgb(10, 20, 30)
+        );
+    }
+
+    #[test]
+    fn carriage_return_overwrites_the_current_line() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"abc\rXY", &config, &mut colors);
+        assert_eq!(screen_text(&session), ["XYc"]);
+        session.append_new_output(b"\r\nnext", &config, &mut colors);
+        assert_eq!(screen_text(&session), ["XYc", "next"]);
+    }
+
+    #[test]
+    fn external_palettes_import_into_palette_fields() {
+        let names = [
+            "black",
+            "red",
+            "green",
+            "yellow",
+            "blue",
+            "purple",
+            "cyan",
+            "white",
+            "brightBlack",
+            "brightRed",
+            "brightGreen",
+            "brightYellow",
+            "brightBlue",
+            "brightPurple",
+            "brightCyan",
+            "brightWhite",
+        ];
+        let mut json = String::from("{\"background\": \"#101010\", \"foreground\": \"#EEEEEE\"");
+        for (index, name) in names.iter().enumerate() {
+            json.push_str(&format!(", \"{name}\": \"#0000{index:02x}\""));
+        }
+        json.push('}');
+        let path = std::env::temp_dir().join(format!("yate-scheme-{}.json", std::process::id()));
+        fs::write(&path, json).unwrap();
+        let palette = import_palette(&path);
+        fs::remove_file(&path).unwrap();
+        let palette = palette.unwrap();
+        assert_eq!(palette.background, [0x10, 0x10, 0x10, 255]);
+        assert_eq!(palette.white, [0xee, 0xee, 0xee, 255]);
+        assert_eq!(palette.red, [0, 0, 1, 255]);
+        assert_eq!(palette.bright_cyan, [0, 0, 14, 255]);
+
+        let plist = "<key>Ansi 1 Color</key><dict>\
+            <key>Red Component</key><real>1.0</real>\
+            <key>Green Component</key><real>0.5</real>\
+            </dict>";
+        let colors = parse_itermcolors(plist);
+        assert_eq!(colors.get("ansi1"), Some(&[255, 128, 0, 255]));
+        assert!(palette_from_named_colors(&colors).is_err());
+    }
+
+    #[test]
+    fn rapid_enters_submit_the_line_once_then_bare_newlines() {
+        assert!(submission_bytes("ls", 0).is_empty());
+        assert_eq!(submission_bytes("ls", 1), b"ls\n");
+        assert_eq!(submission_bytes("ls", 3), b"ls\n\n\n");
+        assert_eq!(submission_bytes("", 1), b"\n");
+    }
+
+    #[test]
+    fn existing_scrollback_relays_out_at_a_new_font_size() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"\x1b[31mred\x1b[0m plain\r\nmore", &config, &mut colors);
+        let job = session.screen.grid.layout_job(&FontId::monospace(20.0));
+        assert!(job.sections.len() > 1);
+        assert!(
+            job.sections
+                .iter()
+                .all(|section| section.format.font_id.size == 20.0)
+        );
+        assert_eq!(
+            effective_font_size(200.0, config.min_font_size)
+                .clamp(MIN_ZOOM_FONT_SIZE, MAX_ZOOM_FONT_SIZE),
+            MAX_ZOOM_FONT_SIZE
+        );
+    }
+
+    #[test]
+    fn piped_stdin_buffers_apart_from_the_session() {
+        let stdin_buffer = Arc::new(Mutex::new(Vec::new()));
+        let finished = spawn_reader(
+            Box::new(std::io::Cursor::new(b"one\n\x1b[1mtwo\x1b[0m\n".to_vec())),
+            stdin_buffer.clone(),
+            Arc::new(OnceLock::new()),
+            1024,
+        );
+        while !finished.load(Ordering::Acquire) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let (mut text, mut partial) = (String::new(), Vec::new());
+        let input = mem::take(&mut *stdin_buffer.lock().unwrap());
+        append_piped_text(&mut text, &mut partial, &input, 1);
+        assert_eq!(text, "two\n");
+        append_piped_text(&mut text, &mut partial, &"日".as_bytes()[..2], 1);
+        assert_eq!((text.as_str(), partial.len()), ("two\n", 2));
+        append_piped_text(&mut text, &mut partial, &"日".as_bytes()[2..], 1);
+        assert_eq!(text, "two\n日");
+
+        let config = Config::default();
+        let session = test_session(&config);
+        assert_eq!(screen_text(&session), [""]);
+    }
+
+    #[test]
+    fn fonts_load_first_in_monospace_or_fail_cleanly() {
+        let dir = std::env::temp_dir();
+        let missing = dir.join(format!("yate-missing-{}.ttf", std::process::id()));
+        assert!(load_font(&missing, FontFallback::Monospace).is_err());
+        let bogus = dir.join(format!("yate-bogus-{}.ttf", std::process::id()));
+        fs::write(&bogus, b"not a font").unwrap();
+        let result = load_font(&bogus, FontFallback::Monospace);
+        fs::remove_file(&bogus).unwrap();
+        assert!(result.is_err());
+
+        let defaults = egui::FontDefinitions::default();
+        let font = dir.join(format!("yate-font-{}.ttf", std::process::id()));
+        fs::write(&font, &defaults.font_data["Hack"].font).unwrap();
+        let fonts = load_font(&font, FontFallback::Proportional);
+        fs::remove_file(&font).unwrap();
+        let fonts = fonts.unwrap();
+        let monospace = &fonts.families[&FontFamily::Monospace];
+        assert_eq!(monospace[0], "user_font");
+        assert_eq!(
+            monospace[1..],
+            defaults.families[&FontFamily::Proportional][..]
+        );
+    }
+
+    #[test]
+    fn resize_keeps_the_top_line_anchored() {
+        let ctx = egui::Context::default();
+        let _ = ctx.run(egui::RawInput::default(), |ctx| {
+            let text = (0..20)
+                .map(|line| format!("line {line:02} {}", "x".repeat(30)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let layout = |width: f32| {
+                let mut job =
+                    LayoutJob::simple(text.clone(), FontId::monospace(10.0), Color32::WHITE, width);
+                job.wrap.break_anywhere = true;
+                ctx.fonts(|fonts| fonts.layout_job(job))
+            };
+            let (wide, narrow) = (layout(f32::INFINITY), layout(100.0));
+            let row_height = wide.rows[0].height();
+            let offset = 5.0 * row_height + 2.0;
+            let anchored = anchored_scroll_offset(&wide, &narrow, offset);
+            let anchor = wide.cursor_from_pos(egui::vec2(0.0, offset));
+            assert_eq!(
+                narrow.cursor_from_pos(egui::vec2(0.0, anchored)).index,
+                anchor.index
+            );
+            assert!(anchored > offset);
+            assert_eq!(anchored_scroll_offset(&wide, &wide, offset), offset);
+        });
+    }
+
+    #[test]
+    fn pager_keys_page_and_quit() {
+        assert_eq!(
+            pager_command(egui::Key::Space),
+            Some(PagerCommand::PageDown)
+        );
+        assert_eq!(pager_command(egui::Key::B), Some(PagerCommand::PageUp));
+        assert_eq!(pager_command(egui::Key::Q), Some(PagerCommand::Quit));
+        assert_eq!(pager_command(egui::Key::A), None);
+        assert_eq!(pager_scroll_offset(100.0, 2, 300.0), 700.0);
+        assert_eq!(pager_scroll_offset(100.0, -1, 300.0), 0.0);
+    }
+
+    #[test]
+    fn utf8_split_across_reads_is_reassembled() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        let bytes = "日本語".as_bytes();
+        session.append_new_output(&bytes[..4], &config, &mut colors);
+        assert_eq!(screen_text(&session), ["日"]);
+        session.append_new_output(&bytes[4..], &config, &mut colors);
+        assert_eq!(screen_text(&session), ["日本語"]);
+    }
+
+    #[test]
+    fn readers_pause_at_the_buffer_bound_until_drained() {
+        let output_buffer = Arc::new(Mutex::new(Vec::new()));
+        let finished = spawn_reader(
+            Box::new(std::io::Cursor::new(vec![b'x'; 5000])),
+            output_buffer.clone(),
+            Arc::new(OnceLock::new()),
+            1024,
+        );
+        thread::sleep(Duration::from_millis(50));
+        let buffered = output_buffer.lock().unwrap().len();
+        assert!((1024..2048).contains(&buffered));
+        assert!(!finished.load(Ordering::Acquire));
+        let mut total = 0;
+        while !finished.load(Ordering::Acquire) || !output_buffer.lock().unwrap().is_empty() {
+            let chunk = mem::take(&mut *output_buffer.lock().unwrap());
+            assert!(chunk.len() < 2048);
+            total += chunk.len();
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(total, 5000);
+    }
+
+    #[test]
+    fn tabs_expand_to_the_next_tab_stop() {
+        assert_eq!(expand_tabs("a\tb", 0, 8), "a       b");
+        assert_eq!(expand_tabs("\tx", 3, 4), " x");
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"a\tb\r\n\tc", &config, &mut colors);
+        assert_eq!(cell_at(&session, 1, 9), Some('b'));
+        assert_eq!(cell_at(&session, 2, 9), Some('c'));
+    }
+
+    #[test]
+    fn palette_reloads_on_change_and_survives_bad_edits() {
+        let path = std::env::temp_dir().join(format!("yate-reload-{}.toml", std::process::id()));
+        let mut app = test_app(Config::default(), path.clone());
+        let red = Colors {
+            red: Color32::from_rgb(9, 8, 7),
+            ..Colors::default()
+        };
+        save_colors(&path, &ColorPalette::from(&red)).unwrap();
+        app.reload_changed_palette();
+        assert_eq!(app.colors.red, Color32::from_rgb(9, 8, 7));
+        fs::write(&path, "red = [").unwrap();
+        app.config_modified = None;
+        app.reload_changed_palette();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(app.colors.red, Color32::from_rgb(9, 8, 7));
+    }
+
+    #[test]
+    fn mirror_shows_the_session_output_without_taking_input() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut app = test_app(config, PathBuf::new());
+        let ctx = egui::Context::default();
+        let mut mirrored = |app: &mut TerminalApp, output: &[u8]| {
+            app.sessions[0].append_new_output(output, &app.config, &mut colors);
+            let input = egui::RawInput {
+                events: vec![egui::Event::Text("typed".to_string())],
+                ..Default::default()
+            };
+            let output = ctx.run(input, |ctx| {
+                let job = app.sessions[0]
+                    .screen
+                    .grid
+                    .layout_job(&FontId::monospace(14.0));
+                app.sessions[0].galley = Some(ctx.fonts(|fonts| fonts.layout_job(job)));
+                app.show_mirror_viewport(ctx);
+            });
+            output
+                .shapes
+                .iter()
+                .filter_map(|clipped| match &clipped.shape {
+                    egui::Shape::Text(text) => Some(text.galley.job.text.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(mirrored(&mut app, b"first").contains(&"first".to_string()));
+        assert!(mirrored(&mut app, b" second").contains(&"first second".to_string()));
+        assert!(app.sessions[0].input_text.is_empty());
+    }
+
+    #[test]
+    fn urls_stop_at_whitespace_and_trailing_punctuation() {
+        let urls = |text: &str| {
+            find_urls(text)
+                .into_iter()
+                .map(|range| text[range].to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            urls("see https://a.example/x. and (http://b.example/y)\nhttps://c.example"),
+            [
+                "https://a.example/x",
+                "http://b.example/y",
+                "https://c.example"
+            ]
+        );
+        assert_eq!(
+            urls("https://en.wikipedia.org/wiki/Rust_(language)"),
+            ["https://en.wikipedia.org/wiki/Rust_(language)"]
+        );
+        assert!(urls("http:// nothing").is_empty());
+
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"open https://exam", &config, &mut colors);
+        session.append_new_output(b"ple.com/path now", &config, &mut colors);
+        let text = session.screen.grid.text();
+        assert_eq!(
+            &text[find_urls(&text)[0].clone()],
+            "https://example.com/path"
+        );
+    }
+
+    #[test]
+    fn unmatched_alt_screen_exit_leaves_the_primary_buffer() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"one\r\ntwo", &config, &mut colors);
+        session.append_new_output(b"\x1b[?1049l", &config, &mut colors);
+        assert_eq!(screen_text(&session), ["one", "two"]);
+        session.append_new_output(b"!", &config, &mut colors);
+        assert_eq!(screen_text(&session), ["one", "two!"]);
+    }
+
+    #[test]
+    fn byte_budget_spreads_a_backlog_across_frames_in_order() {
+        let mut buffer = b"0123456789".to_vec();
+        let mut frames = Vec::new();
+        while !buffer.is_empty() {
+            frames.push(take_budget(&mut buffer, Some(4)));
+        }
+        assert_eq!(frames, [&b"0123"[..], b"4567", b"89"]);
+        let mut buffer = b"abc".to_vec();
+        assert_eq!(take_budget(&mut buffer, None), b"abc");
+        let mut buffer = b"abc".to_vec();
+        assert_eq!(take_budget(&mut buffer, Some(0)), b"a");
+    }
+
+    #[test]
+    fn background_sgr_codes_set_and_reset_the_background() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            b"\x1b[41mX\x1b[0mY\x1b[103mZ\x1b[49mW",
+            &config,
+            &mut colors,
+        );
+        let background =
+            |column: usize| session.screen.grid.lines[0].cells[column].format.background;
+        assert_eq!(background(0), colors.red);
+        assert_eq!(background(2), colors.bright_yellow);
+        assert_eq!(background(1), Color32::TRANSPARENT);
+        assert_eq!(background(3), Color32::TRANSPARENT);
+    }
+
+    #[test]
+    fn cursor_moves_while_scrolled_up_are_kept_for_the_bottom() {
+        let mut app = test_app(Config::default(), PathBuf::new());
+        let mut colors = Colors::default();
+        app.sessions[0].stick_to_bottom = false;
+        app.sessions[0].scroll_offset_y = 0.0;
+        let output = (0..60)
+            .map(|line| format!("line {line}\r\n"))
+            .collect::<String>();
+        app.sessions[0].append_new_output(output.as_bytes(), &app.config, &mut colors);
+        app.sessions[0].append_new_output(b"\x1b[3;5Hx\x1b[2A", &app.config, &mut colors);
+        assert!(!app.sessions[0].stick_to_bottom);
+        let session = &app.sessions[0];
+        let top = session.screen.grid.screen_top(session.screen_rows);
+        assert_eq!(
+            (session.screen.grid.row - top, session.screen.grid.column),
+            (0, 5)
+        );
+        assert_eq!(cell_at(session, 3, 5), Some('x'));
+    }
+
+    #[test]
+    fn backspace_moves_back_for_overwrites() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"abc\x08\x08X", &config, &mut colors);
+        assert_eq!(screen_text(&session), ["aXc"]);
+        session.append_new_output(b"\x08 \x08", &config, &mut colors);
+        assert_eq!(screen_text(&session), ["a c"]);
+        session.append_new_output(b"\r\x08\x08y", &config, &mut colors);
+        assert_eq!(screen_text(&session), ["y c"]);
+        assert_eq!(
+            encode_key(
+                egui::Key::Backspace,
+                egui::Modifiers::NONE,
+                TerminalModes::new(true)
+            )
+            .as_deref(),
+            Some(&b"\x7f"[..])
+        );
+    }
+
+    #[test]
+    fn quiet_startup_never_writes_default_colors() {
+        let path = std::env::temp_dir().join(format!("yate-quiet-{}.toml", std::process::id()));
+        let colors = load_colors_or_default(&path, false, false);
+        assert_eq!(colors.red, Colors::default().red);
+        assert!(!path.exists());
+        load_colors_or_default(&path, true, false);
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+
+        let path = config_file_path("yate-missing-test.toml", false);
+        let dir = path.parent().and_then(Path::file_name);
+        if dirs::config_dir().is_some() {
+            assert_eq!(dir, Some("YATE".as_ref()));
+        }
+    }
+
+    #[test]
+    fn alt_screen_is_discarded_on_exit() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        for (enter, exit) in [
+            (&b"\x1b[?1049h"[..], &b"\x1b[?1049l"[..]),
+            (b"\x1b[?47h", b"\x1b[?47l"),
+        ] {
+            let mut session = test_session(&config);
+            session.append_new_output(b"prompt$ vim", &config, &mut colors);
+            session.append_new_output(enter, &config, &mut colors);
+            session.append_new_output(b"\x1b[2;1H~ editor", &config, &mut colors);
+            assert_eq!(cell_at(&session, 2, 1), Some('~'));
+            session.append_new_output(exit, &config, &mut colors);
+            assert_eq!(session.screen.grid.text(), "prompt$ vim");
+        }
+    }
+
+    #[test]
+    fn bell_sets_the_flash_without_visible_output() {
+        let mut colors = Colors::default();
+        for (bell, flashes) in [(BellStyle::Visual, true), (BellStyle::None, false)] {
+            let config = Config {
+                bell,
+                ..Config::default()
+            };
+            let mut session = test_session(&config);
+            session.append_new_output(b"a\x07b", &config, &mut colors);
+            assert_eq!(screen_text(&session), ["ab"]);
+            assert_eq!(session.bell, flashes);
+        }
+        let mut app = test_app(Config::default(), PathBuf::new());
+        let resting = app.base_background();
+        app.sessions[0].bell_flash = Some(Instant::now());
+        assert_ne!(app.base_background(), resting);
+        app.sessions[0].bell_flash = Some(Instant::now() - BELL_FLASH_DURATION);
+        assert_eq!(app.base_background(), resting);
+    }
+
+    #[test]
+    fn restored_geometry_is_clamped_onto_the_monitor() {
+        let geometry = WindowGeometry {
+            width: f32::NAN,
+            height: 1.0,
+            x: Some(f32::INFINITY),
+            y: Some(40.0),
+        }
+        .clamped();
+        assert_eq!(
+            (geometry.width, geometry.height, geometry.x, geometry.y),
+            (
+                1920.0_f32.min(MAX_WINDOW_SIZE.x),
+                MIN_WINDOW_SIZE.y,
+                None,
+                Some(40.0)
+            )
+        );
+        let monitor = egui::vec2(1920.0, 1080.0);
+        let size = egui::vec2(800.0, 600.0);
+        assert_eq!(
+            visible_position(egui::pos2(5000.0, -30.0), size, monitor),
+            egui::pos2(1120.0, 0.0)
+        );
+        let pty = estimated_pty_size(egui::vec2(850.0, 340.0), 14.0);
+        assert_eq!((pty.rows, pty.cols), (20, 101));
+    }
+
+    #[test]
+    fn scrollback_exports_plain_and_sgr_text() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            b"\x1b[1;31mred\x1b[0m plain\r\n\x1b[4mnext",
+            &config,
+            &mut colors,
+        );
+        assert_eq!(session.scrollback_to_plain(), "red plain\nnext");
+        let sgr = session.scrollback_to_sgr();
+        assert!(sgr.ends_with("\x1b[0m"));
+        assert_eq!(strip_ansi(&sgr), "red plain\nnext");
+        let [r, g, b, _] = colors.red.to_array();
+        assert!(sgr.starts_with(&format!("\x1b[0;38;2;{r};{g};{b};1mred\x1b[0;38;2;")));
+        let plain = &sgr[sgr.find("red").unwrap() + 3..sgr.find(" plain").unwrap()];
+        assert!(!plain.ends_with(";1m") && !plain.ends_with(";4m"));
+        assert!(sgr[..sgr.find("next").unwrap()].ends_with(";4m"));
+    }
+
+    #[test]
+    fn faint_dims_and_strikethrough_strokes_the_text() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(b"n\x1b[2mf\x1b[22mn\x1b[9ms\x1b[29mn", &config, &mut colors);
+        let format = |column: usize| session.screen.grid.lines[0].cells[column].format.clone();
+        assert_eq!(format(0).color, colors.white);
+        assert_ne!(format(1).color, colors.white);
+        assert_eq!(format(2).color, colors.white);
+        assert_eq!(format(0).strikethrough, egui::Stroke::NONE);
+        assert_ne!(format(3).strikethrough, egui::Stroke::NONE);
+        assert_eq!(format(4).strikethrough, egui::Stroke::NONE);
+    }
+
+    #[test]
+    fn grid_keeps_counters_in_step_with_its_cells() {
+        let mut grid = Grid::default();
+        let counts = |grid: &Grid| {
+            let text = grid.text();
+            assert_eq!(
+                (grid.len(), grid.char_count()),
+                (text.len(), text.chars().count())
+            );
+            text
+        };
+        grid.write("héllo\nwörld", TextFormat::default(), false);
+        assert_eq!(counts(&grid), "héllo\nwörld");
+        assert_eq!(grid.cursor_offset(), (grid.len(), grid.char_count()));
+        grid.move_cursor(0, 7, 2, 80);
+        grid.put('!');
+        assert_eq!(counts(&grid), "héllo  !\nwörld");
+        assert_eq!(grid.offset(1, 1), (11, 10));
+        grid.move_cursor(0, 1, 2, 80);
+        grid.put('e');
+        grid.blank_cells(1, 0..2);
+        assert_eq!(counts(&grid), "hello  !\n  rld");
+        grid.truncate_line(0, 2);
+        assert_eq!(counts(&grid), "he\n  rld");
+        assert_eq!(grid.trim(0, 10), (3, 3));
+        assert_eq!(counts(&grid), "  rld");
+        assert_eq!((grid.row, grid.column), (0, 0));
+    }
+
+    #[test]
+    fn stick_to_bottom_transitions_follow_the_scroll() {
+        assert!(!stick_to_bottom_after_scroll(true, 400.0, 500.0, false));
+        assert!(stick_to_bottom_after_scroll(false, 500.0, 500.0, false));
+        assert!(stick_to_bottom_after_scroll(true, 500.0, 540.0, true));
+        assert!(!stick_to_bottom_after_scroll(false, 500.0, 540.0, true));
+        assert!(stick_to_bottom_after_scroll(
+            false,
+            500.0 - STICK_TO_BOTTOM_TOLERANCE / 2.0,
+            500.0,
+            false
+        ));
+
+        let command =
+            |key, shift, line_mode, input_empty| scroll_command(key, shift, line_mode, input_empty);
+        assert_eq!(
+            command(egui::Key::PageUp, false, true, false),
+            Some(ScrollCommand::PageUp)
+        );
+        assert_eq!(command(egui::Key::PageDown, false, false, true), None);
+        assert_eq!(
+            command(egui::Key::PageDown, true, false, true),
+            Some(ScrollCommand::PageDown)
+        );
+        assert_eq!(command(egui::Key::Home, true, true, false), None);
+        assert_eq!(
+            command(egui::Key::Home, true, true, true),
+            Some(ScrollCommand::Top)
+        );
+        assert_eq!(
+            command(egui::Key::End, true, false, false),
+            Some(ScrollCommand::Bottom)
+        );
+    }
+
+    #[test]
+    fn exited_shells_leave_a_notice_in_the_scrollback() {
+        let status = portable_pty::ExitStatus::with_exit_code(2);
+        assert_eq!(exit_notice(&status, false), "[process exited with code 2]");
+        let status = portable_pty::ExitStatus::with_exit_code(0);
+        assert_eq!(
+            exit_notice(&status, true),
+            "[process exited] Press Enter to restart."
+        );
+
+        let config = Config {
+            on_shell_exit: ShellExitAction::Notice,
+            ..Config::default()
+        };
+        let passthrough = ["true".to_string()];
+        let shell = spawn_shell(
+            &config,
+            &passthrough,
+            PtySize::default(),
+            Arc::new(OnceLock::new()),
+            None,
+        )
+        .unwrap();
+        let mut app = test_app(config, PathBuf::new());
+        app.sessions[0].shell = Some(shell);
+        let ctx = egui::Context::default();
+        let started_at = Instant::now();
+        while app.sessions[0].shell.is_some() {
+            assert!(started_at.elapsed() < Duration::from_secs(10));
+            if let Some(shell) = &app.sessions[0].shell {
+                shell.output_buffer.lock().unwrap().clear();
+            }
+            app.poll_shell_exit(0, &ctx);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            screen_text(&app.sessions[0]).last().unwrap(),
+            "[process exited]"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tabs_open_switch_and_close_their_own_sessions() {
+        let mut app = test_app(Config::default(), PathBuf::new());
+        app.passthrough = vec!["cat".to_string()];
+        app.open_session();
+        app.open_session();
+        assert_eq!((app.sessions.len(), app.active_session), (3, 2));
+        assert!(
+            app.sessions[1..]
+                .iter()
+                .all(|session| session.shell.is_some())
+        );
+
+        let mut colors = Colors::default();
+        for (index, session) in app.sessions.iter_mut().enumerate() {
+            session.append_new_output(format!("tab {index}").as_bytes(), &app.config, &mut colors);
+        }
+        app.switch_session(0);
+        let session = app.session_mut();
+        (session.stick_to_bottom, session.scroll_offset_y) = (false, 120.0);
+        (session.pager, session.search_query) = (true, Some("tab".to_string()));
+        app.switch_session(1);
+        assert!(app.session().stick_to_bottom && !app.session().pager);
+        assert_eq!(screen_text(app.session()), ["tab 1"]);
+        app.switch_session(0);
+        let session = app.session();
+        assert!(!session.stick_to_bottom && session.pager);
+        assert_eq!(session.scroll_offset_y, 120.0);
+        assert_eq!(session.search_query.as_deref(), Some("tab"));
+
+        let ctx = egui::Context::default();
+        for index in [1, 2] {
+            app.sessions[index].append_new_output(b"\x1b[?1004h", &app.config, &mut colors);
+        }
+        app.switch_session(2);
+        app.sessions[1].bell = true;
+        app.poll_session(1, &ctx);
+        assert!(app.sessions[1].unseen_bell);
+        app.switch_session(1);
+        assert!(!app.sessions[1].unseen_bell);
+        let echoed = |app: &TerminalApp, index: usize, report: &str| {
+            let shell = app.sessions[index].shell.as_ref().unwrap();
+            let started_at = Instant::now();
+            while started_at.elapsed() < Duration::from_secs(5) {
+                let output = shell.output_buffer.lock().unwrap();
+                if String::from_utf8_lossy(&output).contains(report) {
+                    return true;
+                }
+                drop(output);
+                thread::sleep(Duration::from_millis(10));
+            }
+            false
+        };
+        assert!(echoed(&app, 2, "[O"));
+        assert!(echoed(&app, 1, "[I"));
+        app.close_session(0, &ctx);
+        assert_eq!(screen_text(app.session()), ["tab 1"]);
+        app.close_session(1, &ctx);
+        assert_eq!(screen_text(app.session()), ["tab 1"]);
+        let pid = app
+            .session()
+            .shell
+            .as_ref()
+            .unwrap()
+            .child
+            .process_id()
+            .unwrap();
+        let output = ctx.run(egui::RawInput::default(), |ctx| app.close_session(0, ctx));
+        assert_eq!(app.sessions.len(), 1);
+        assert!(app.session().shell.is_none());
+        assert_eq!(unsafe { libc::kill(pid as libc::pid_t, 0) }, -1);
+        assert!(
+            output.viewport_output[&egui::ViewportId::ROOT]
+                .commands
+                .contains(&egui::ViewportCommand::Close)
+        );
+    }
+
+    #[test]
+    fn resizes_reach_the_pty_only_when_the_grid_changes() {
+        let config = Config::default();
+        let mut session = test_session(&config);
+        let passthrough = ["cat".to_string()];
+        session.shell = Some(
+            spawn_shell(
+                &config,
+                &passthrough,
+                PtySize::default(),
+                Arc::new(OnceLock::new()),
+                None,
+            )
+            .unwrap(),
+        );
+        let size = |rows, cols, pixel_width| PtySize {
+            rows,
+            cols,
+            pixel_width,
+            pixel_height: 0,
+        };
+        session.resize_pty(size(30, 100, 800));
+        let master_size = session.shell.as_ref().unwrap().master.get_size().unwrap();
+        assert_eq!((master_size.rows, master_size.cols), (30, 100));
+        session.resize_pty(size(30, 100, 900));
+        assert_eq!(session.pty_size.pixel_width, 800);
+        let shell = session.shell.as_mut().unwrap();
+        let _ = shell.child.kill();
+        let _ = shell.child.wait();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tab_keys_work_in_both_input_modes_and_never_reach_the_shell() {
+        let press = |key, modifiers| egui::Event::Key {
+            key,
+            physical_key: None,
+            pressed: true,
+            repeat: false,
+            modifiers,
+        };
+        // winit reports Ctrl as both ctrl and command outside macOS.
+        let ctrl = egui::Modifiers::CTRL | egui::Modifiers::COMMAND;
+        let mut app = test_app(Config::default(), PathBuf::new());
+        app.passthrough = vec!["cat".to_string()];
+        let ctx = egui::Context::default();
+        let frame = |app: &mut TerminalApp, event: egui::Event| {
+            let input = egui::RawInput {
+                events: vec![event],
+                ..Default::default()
+            };
+            let _ = ctx.run(input, |ctx| {
+                app.handle_tab_keys(ctx);
+                ctx.input(|i| assert!(i.events.is_empty()));
+            });
+        };
+        for input_mode in [InputMode::Line, InputMode::Raw] {
+            app.input_mode = input_mode;
+            frame(&mut app, press(egui::Key::T, ctrl));
+            assert_eq!((app.sessions.len(), app.active_session), (2, 1));
+            frame(&mut app, press(egui::Key::Tab, ctrl));
+            assert_eq!(app.active_session, 0);
+            let shift_ctrl = ctrl | egui::Modifiers::SHIFT;
+            frame(&mut app, press(egui::Key::Tab, shift_ctrl));
+            assert_eq!(app.active_session, 1);
+            frame(&mut app, press(egui::Key::W, ctrl));
+            assert_eq!((app.sessions.len(), app.active_session), (1, 0));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tabs_take_their_scrollback_limit_from_the_config() {
+        let config: Config = toml::from_str("tab_scrollback_lines = [3, 5]").unwrap();
+        let mut app = test_app(config, PathBuf::new());
+        app.passthrough = vec!["cat".to_string()];
+        app.open_session();
+        app.open_session();
+        let limits: Vec<_> = app
+            .sessions
+            .iter()
+            .map(|session| session.scrollback_lines)
+            .collect();
+        assert_eq!(limits, [3, 5, 10_000]);
+
+        let output = "line\n".repeat(10);
+        let mut colors = Colors::default();
+        for session in &mut app.sessions[..2] {
+            session.append_new_output(output.as_bytes(), &app.config, &mut colors);
+            while session.trim_scrollback(usize::MAX) {}
+        }
+        assert_eq!(app.sessions[0].screen.grid.newline_count(), 3);
+        assert_eq!(app.sessions[1].screen.grid.newline_count(), 5);
+        for index in (1..app.sessions.len()).rev() {
+            app.close_session(index, &egui::Context::default());
+        }
+    }
+
+    #[test]
+    fn erasing_earlier_text_keeps_prompt_and_command_regions_on_their_text() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut session = test_session(&config);
+        session.append_new_output(
+            "λ header\n\x1b]133;A\x07$ \x1b]133;B\x07ls\n\x1b]133;C\x07out\n\x1b]133;D;0\x07\x1b[1;1H\x1b[K"
+                .as_bytes(),
+            &config,
+            &mut colors,
+        );
+        let text = session.screen.grid.text();
+        let region_text = |region: &Range<usize>| -> String {
+            text.chars().skip(region.start).take(region.len()).collect()
+        };
+        assert_eq!(text.lines().next(), Some(""));
+        let prompts: Vec<_> = session
+            .screen
+            .prompt_regions
+            .iter()
+            .map(region_text)
+            .collect();
+        assert_eq!(prompts, ["$ "]);
+        let commands: Vec<_> = session
+            .screen
+            .command_regions
+            .iter()
+            .map(region_text)
+            .collect();
+        assert_eq!(commands, ["out\n"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_reader_adds_sources_while_polling() {
+        use std::os::unix::net::UnixStream;
+        let reader = SharedReader::default();
+        let (_idle_writer, idle) = UnixStream::pair().unwrap();
+        reader.add(
+            fs::File::from(std::os::fd::OwnedFd::from(idle)),
+            Arc::new(Mutex::new(Vec::new())),
+        );
+        let polling = reader.clone();
+        let poll =
+            thread::spawn(move || polling.read_ready(MIN_BUFFERED_OUTPUT, Duration::from_secs(2)));
+        thread::sleep(Duration::from_millis(100));
+        let (_writer, added) = UnixStream::pair().unwrap();
+        let started_at = Instant::now();
+        reader.add(
+            fs::File::from(std::os::fd::OwnedFd::from(added)),
+            Arc::new(Mutex::new(Vec::new())),
+        );
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+        assert!(!poll.join().unwrap());
+        assert_eq!(reader.sources.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn show_terminal_lays_out_the_output_and_sizes_the_grid() {
+        let config = Config::default();
+        let mut colors = Colors::default();
+        let mut app = test_app(config, PathBuf::new());
+        app.sessions[0].append_new_output(b"hello", &app.config, &mut colors);
+        let ctx = egui::Context::default();
+        let input = egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(400.0, 300.0),
+            )),
+            ..Default::default()
+        };
+        let output = ctx.run(input, |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| app.show_terminal(ctx, ui));
+        });
+        let session = app.session();
+        assert_eq!(
+            session
+                .galley
+                .as_ref()
+                .map(|galley| galley.job.text.as_str()),
+            Some("hello")
+        );
+        assert!(session.screen_rows > 0 && session.screen_cols > 0);
+        assert!(app.viewport_width > 0.0 && app.viewport_height > 0.0);
+        let cursor_fill = app.colors.white.gamma_multiply(0.6);
+        assert!(output.shapes.iter().any(|clipped| {
+            matches!(&clipped.shape, egui::Shape::Rect(rect) if rect.fill == cursor_fill)
+        }));
+    }
+}